@@ -1,6 +1,15 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, builder::RangedU64ValueParser};
 use std::path::PathBuf;
 
+use crate::binarise::BinarisationOptions;
+use crate::compare::{Significance, parse_confidence};
+use crate::eval::breakdown::BreakdownFormat;
+use crate::head::LexicalisationMode;
+use crate::markers::MarkerOptions;
+use crate::output::Output;
+use crate::smoothing::SignatureOptions;
+use crate::transform::Step;
+
 #[derive(Parser)]
 #[command(version, about, long_about=None, disable_help_flag=true)]
 pub struct Args {
@@ -14,7 +23,44 @@ pub enum Commands {
         /// Sets the name of the Grammar files ([GRAMMAR].rules, [GRAMMAR].lexicon, [GRAMMAR].words)
         #[arg()]
         grammar: Option<String>,
+        /// also write the absolute rule counts to [GRAMMAR].counts
+        #[arg(short, long, requires = "grammar")]
+        counts: bool,
+        /// also write a suffix model of the words occurring at most THRESHOLD times to
+        /// [GRAMMAR].suffixes
+        #[arg(long, value_name = "THRESHOLD", requires = "grammar")]
+        suffix_model: Option<u64>,
+        /// add the counts of the input trees to the counts in this file
+        #[arg(short, long, value_name = "COUNTS")]
+        add: Option<PathBuf>,
+        /// read the trees from these files and directories in parallel instead of stdin
+        #[arg(short, long, value_name = "TREEBANK", num_args = 1..)]
+        input: Vec<PathBuf>,
+        /// additive smoothing of the rules with this pseudo count over the observed right hand sides
+        /// of the lengths a non-terminal was seen with, unary rules are not added
+        #[arg(short, long, value_name = "ALPHA")]
+        rule_smoothing: Option<f64>,
+        /// additive smoothing of the lexicon with this pseudo count over all observed words, the
+        /// words unseen with a tag get the rule TAG UNK
+        #[arg(short, long, value_name = "ALPHA")]
+        lexicon_smoothing: Option<f64>,
+        /// back off markovised rules A|<…> to less specific contexts with this weight
+        #[arg(short, long, value_name = "WEIGHT")]
+        backoff: Option<f64>,
+        /// annotate the non-terminals with the tag or the tag and word of their head
+        #[arg(long, value_name = "MODE")]
+        lexicalise: Option<LexicalisationMode>,
+        /// head percolation table used for lexicalisation (default: Collins)
+        #[arg(long, value_name = "HEAD_RULES")]
+        head_rules: Option<PathBuf>,
+        /// head words occurring at most this often are backed off to their tag
+        #[arg(long, default_value_t = 1)]
+        lexical_threshold: u64,
+        #[command(flatten)]
+        markers: MarkerOptions,
     },
+    // the signature options only apply to --smoothing
+    #[command(mut_group("SignatureOptions", |group| group.requires("smoothing")))]
     Parse {
         #[arg(value_name = "RULES")]
         rules: PathBuf,
@@ -32,6 +78,38 @@ pub enum Commands {
         /// replace unknown words with smoothing
         #[arg(short, long)]
         smoothing: bool,
+        #[command(flatten)]
+        signature: SignatureOptions,
+        /// give unknown words the tag weights of this suffix model written by induce
+        #[arg(long, value_name = "SUFFIXES")]
+        suffix_model: Option<PathBuf>,
+        /// give unknown words the lexical rules of their most similar known words in this
+        /// word2vec or GloVe text file
+        #[arg(long, value_name = "VECTORS")]
+        embeddings: Option<PathBuf>,
+        /// the number of similar known words for --embeddings
+        #[arg(
+            long,
+            value_name = "COUNT",
+            default_value_t = 5,
+            requires = "embeddings"
+        )]
+        neighbours: usize,
+        /// map unknown words to known spelling variants after lowercasing, unicode and digit
+        /// folding or within the edit distance, before unking or smoothing
+        #[arg(long)]
+        normalise: bool,
+        /// the largest edit distance for --normalise, at most a third of the word length is used
+        #[arg(
+            long,
+            value_name = "EDITS",
+            default_value_t = 2,
+            requires = "normalise"
+        )]
+        edit_distance: usize,
+        /// report how unknown words are mapped on stderr
+        #[arg(long)]
+        verbose: bool,
         /// use beam search with threshhold
         #[arg(short, long)]
         threshold_beam: Option<u64>,
@@ -41,21 +119,132 @@ pub enum Commands {
         /// use a star search
         #[arg(short, long)]
         astar: Option<PathBuf>,
+        /// parse with a lexicalised grammar: back off to the head tag and the bare label for
+        /// unseen head words and remove the head annotations from the output
+        #[arg(long)]
+        lexicalised: bool,
+        /// the weight of each back-off step of --lexicalised
+        #[arg(
+            long,
+            value_name = "WEIGHT",
+            default_value_t = 0.1,
+            requires = "lexicalised"
+        )]
+        lexical_backoff: f64,
+        /// project the latent subcategories of a split-merge grammar back to the original labels
+        #[arg(long)]
+        latent: bool,
+        /// write the words -LRB-, -RRB- … as the brackets they stand for
+        #[arg(long)]
+        restore_brackets: bool,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// re-estimates a grammar on raw sentences from stdin with the inside-outside algorithm
+    #[command(mut_group("SignatureOptions", |group| group.requires("smoothing")))]
+    TrainEm {
+        #[arg(value_name = "RULES")]
+        rules: PathBuf,
+        #[arg(value_name = "LEXICON")]
+        lexicon: PathBuf,
+        /// Sets the name of the re-estimated Grammar files ([GRAMMAR].rules, [GRAMMAR].lexicon, [GRAMMAR].words)
+        #[arg()]
+        grammar: Option<String>,
+        #[arg(short, long, default_value_t=String::from("ROOT"))]
+        initial_nonterminal: String,
+        /// number of EM iterations
+        #[arg(short = 'n', long, default_value_t = 10)]
+        iterations: u64,
+        /// replace unknown words
+        #[arg(short, long)]
+        unking: bool,
+        /// replace unknown words with smoothing
+        #[arg(short, long)]
+        smoothing: bool,
+        #[command(flatten)]
+        signature: SignatureOptions,
+    },
+    /// refines a grammar by splitting its non-terminals into latent subcategories, trained with
+    /// EM on the binarised trees from stdin
+    SplitMerge {
+        #[arg(value_name = "RULES")]
+        rules: PathBuf,
+        #[arg(value_name = "LEXICON")]
+        lexicon: PathBuf,
+        /// Sets the name of the refined Grammar files ([GRAMMAR].rules, [GRAMMAR].lexicon, [GRAMMAR].words)
+        #[arg()]
+        grammar: Option<String>,
+        #[arg(short, long, default_value_t=String::from("ROOT"))]
+        initial_nonterminal: String,
+        /// number of split-merge cycles
+        #[arg(short, long, default_value_t = 2)]
+        cycles: u64,
+        /// number of EM iterations after each split and merge
+        #[arg(short = 'n', long, default_value_t = 10)]
+        iterations: u64,
+        /// fraction of the splits that are merged back
+        #[arg(short, long, default_value_t = 0.5)]
+        merge_fraction: f64,
+        /// seed for the noise added to split rules
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
     },
     Binarise {
-        #[arg(short, long, default_value_t=999)]
-        horizontal: u64,
-        #[arg(short, long, default_value_t=1)]
-        vertical: u64,
+        #[command(flatten)]
+        binarisation: BinarisationOptions,
+        #[command(flatten)]
+        output: Output,
+    },
+    Debinarise {
+        /// write the words -LRB-, -RRB- … as the brackets they stand for
+        #[arg(long)]
+        restore_brackets: bool,
+        #[command(flatten)]
+        markers: MarkerOptions,
+        #[command(flatten)]
+        output: Output,
+    },
+    /// preprocesses treebank trees from stdin, by default with all steps
+    Transform {
+        /// the steps to run, in this order
+        #[arg(short, long, value_enum, value_delimiter = ',')]
+        steps: Vec<Step>,
+        /// label of the root that replaces the empty-label root
+        #[arg(short, long, default_value_t=String::from("ROOT"))]
+        root: String,
+        /// write the words -LRB-, -RRB- … as the brackets they stand for
+        #[arg(long)]
+        restore_brackets: bool,
+        #[command(flatten)]
+        output: Output,
     },
-    Debinarise {},
     Unk {
+        /// words occurring at most this often are unknown, a .words vocabulary is used as it is
         #[arg(short, long)]
         threshold: u64,
+        /// take the known words from this .words or .counts file of the training data instead
+        /// of the trees from stdin
+        #[arg(long, value_name = "VOCABULARY")]
+        vocabulary: Option<PathBuf>,
+        /// read the trees from these files in two passes instead of keeping the trees from
+        /// stdin in memory
+        #[arg(value_name = "INPUT")]
+        inputs: Vec<PathBuf>,
     },
     Smooth {
+        /// words occurring at most this often are unknown, a .words vocabulary is used as it is
         #[arg(short, long)]
         threshold: u64,
+        #[command(flatten)]
+        signature: SignatureOptions,
+        /// take the known words from this .words or .counts file of the training data instead
+        /// of the trees from stdin
+        #[arg(long, value_name = "VOCABULARY")]
+        vocabulary: Option<PathBuf>,
+        /// read the trees from these files in two passes instead of keeping the trees from
+        /// stdin in memory
+        #[arg(value_name = "INPUT")]
+        inputs: Vec<PathBuf>,
     },
     Outside {
         #[arg(value_name = "RULES")]
@@ -67,4 +256,59 @@ pub enum Commands {
         #[arg(short, long, default_value_t=String::from("ROOT"))]
         initial_nonterminal: String,
     },
+    /// scores predicted trees against gold trees with the PARSEVAL measures, like evalb
+    Eval {
+        #[arg(value_name = "GOLD")]
+        gold: PathBuf,
+        #[arg(value_name = "PREDICTED")]
+        predicted: PathBuf,
+        /// evalb parameter file (e.g. COLLINS.prm)
+        #[arg(short, long, value_name = "PARAMETERS")]
+        parameters: Option<PathBuf>,
+        /// also break the scores down by label, sentence length and error type
+        #[arg(short, long, value_name = "FORMAT")]
+        breakdown: Option<BreakdownFormat>,
+    },
+    /// checks that debinarise restores the trees from stdin after binarise, reports the trees
+    /// that differ and exits with 1 if there are any
+    VerifyTransform {
+        #[command(flatten)]
+        binarisation: BinarisationOptions,
+    },
+    /// draws the trees from stdin in the terminal
+    Show {
+        /// highlight the constituents that are not in the corresponding tree of this file
+        #[arg(short, long, value_name = "GOLD")]
+        gold: Option<PathBuf>,
+        /// draw with ASCII instead of Unicode box characters
+        #[arg(short, long)]
+        ascii: bool,
+    },
+    /// tests whether the F1 difference of two parser outputs on the same gold trees is significant
+    Compare {
+        #[arg(value_name = "GOLD")]
+        gold: PathBuf,
+        #[arg(value_name = "FIRST")]
+        first: PathBuf,
+        #[arg(value_name = "SECOND")]
+        second: PathBuf,
+        /// evalb parameter file (e.g. COLLINS.prm)
+        #[arg(short, long, value_name = "PARAMETERS")]
+        parameters: Option<PathBuf>,
+        #[arg(short, long, value_enum, default_value_t = Significance::Bootstrap)]
+        method: Significance,
+        /// number of bootstrap samples or random permutations
+        #[arg(
+            short = 'n',
+            long,
+            default_value_t = 10000,
+            value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        samples: usize,
+        /// coverage of the bootstrap confidence intervals, between 0 and 1
+        #[arg(short, long, default_value_t = 0.95, value_parser = parse_confidence)]
+        confidence: f64,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
 }
@@ -121,11 +121,8 @@ impl ViterbiScore {
         for item in &self.all_nonterminals {
             let item_pos = usize::from(*item);
             let rules_with_item = self.all_rules.get(item);
-            if rules_with_item.is_none() {
-                self.r#in[item_pos] = 0f64;
-            } else {
+            if let Some(rules_with_item) = rules_with_item {
                 self.r#in[item_pos] = rules_with_item
-                    .unwrap()
                     .iter()
                     .filter_map(|(rhs, f)| match *rhs {
                         Rhs::Unary(e) => (!e.is_nonterminal()).then_some(f),
@@ -136,6 +133,8 @@ impl ViterbiScore {
                     .max()
                     .unwrap_or_else(|| NotNan::new(0f64).unwrap())
                     .into();
+            } else {
+                self.r#in[item_pos] = 0f64;
             }
         }
         let mut changed = true;
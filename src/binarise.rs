@@ -1,55 +1,111 @@
-use std::{cmp::max, io, process::exit};
+use std::{cmp::max, io, ops::Range, path::PathBuf};
 
-use crate::induce::parse_tree::{ParseTree, element};
+use clap::{Args, ValueEnum};
 
-pub fn binarise(horizontal: u64, vertical: u64) {
-    for (line_number, line) in io::stdin().lines().enumerate() {
-        let Ok(line) = line else { continue };
-        let transformed_line = binarise_line(line, horizontal, vertical);
-        if let Some(transformed_line) = transformed_line {
-            println!("{}", transformed_line);
-        } else {
-            eprintln!("error while transforming line {}", line_number + 1);
-            exit(1);
+use crate::{
+    head::HeadRules,
+    induce::parse_tree::{ParseTree, read_trees},
+    markers::{Annotation, MarkerOptions, Markers},
+    output::{OutputFormat, format_tree},
+};
+
+/// in which order the children of a node with more than two children are split off
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Factorisation {
+    /// split off the first child, the intermediate nodes are right children
+    #[default]
+    Right,
+    /// split off the last child, the intermediate nodes are left children
+    Left,
+    /// split off the children left of the head from the outside in, then the ones right of it,
+    /// so the head is the lowest child
+    Head,
+}
+
+/// the options of the binarisation
+pub struct Binarisation {
+    /// number of siblings kept in the label of intermediate nodes, 1 keeps none
+    pub horizontal: u64,
+    /// number of parents annotated to a label, 1 annotates none
+    pub vertical: u64,
+    pub factorisation: Factorisation,
+    /// finds the head for head-outward factorisation
+    pub head_rules: HeadRules,
+    pub markers: Markers,
+}
+
+// the binarisation options shared by `binarise` and `verify-transform`
+#[derive(Args, Clone, Debug)]
+pub struct BinarisationOptions {
+    #[arg(short, long, default_value_t = 999)]
+    pub horizontal: u64,
+    #[arg(short, long, default_value_t = 1)]
+    pub vertical: u64,
+    /// in which order the children are split off
+    #[arg(short, long, value_enum, default_value_t = Factorisation::Right)]
+    pub factorisation: Factorisation,
+    /// head percolation table used for head-outward factorisation (default: Collins)
+    #[arg(long, value_name = "HEAD_RULES")]
+    pub head_rules: Option<PathBuf>,
+    #[command(flatten)]
+    pub markers: MarkerOptions,
+}
+
+impl BinarisationOptions {
+    pub fn binarisation(&self) -> Binarisation {
+        Binarisation {
+            horizontal: self.horizontal,
+            vertical: self.vertical,
+            factorisation: self.factorisation,
+            head_rules: self
+                .head_rules
+                .as_ref()
+                .map_or_else(HeadRules::collins, |path| HeadRules::from_file(path)),
+            markers: self.markers.markers(),
         }
     }
 }
 
-fn binarise_line(line: String, horizontal: u64, vertical: u64) -> Option<String> {
-    let Ok((remaining, tree)) = element(&line) else {
-        return None;
-    };
-    if remaining.trim() != "" {
-        return None;
+pub fn binarise(binarisation: &Binarisation, output_format: OutputFormat) {
+    for tree_text in read_trees(io::stdin().lock(), "stdin") {
+        let tree = tree_text.parse_or_exit("stdin");
+        let transformed_tree = binarise_tree(tree, binarisation, &[], None);
+        println!("{}", format_tree(&transformed_tree, output_format));
     }
-
-    let transformed_tree = binarise_tree(tree, horizontal, vertical, &[], true);
-
-    Some(transformed_tree.to_string())
 }
 
-fn binarise_tree(
+/// binarises an original node (`head = None`) or an intermediate node with the position of
+/// the head among its children
+///
+/// the labels of the nodes above the preterminals are escaped with the markers, the labels of
+/// intermediate nodes already are
+pub fn binarise_tree(
     mut tree: ParseTree<&str>,
-    horizontal: u64,
-    vertical: u64,
+    binarisation: &Binarisation,
     parents: &[String],
-    original: bool,
+    head: Option<usize>,
 ) -> ParseTree<String> {
-    let root = tree.root.to_string();
-    let mut transformed_tree = ParseTree::new(root.clone());
-
     // node is preterminal
     if tree.children.len() == 1 && tree.children[0].is_leaf() {
+        let mut transformed_tree = ParseTree::new(tree.root.to_string());
         transformed_tree
             .children
             .push(ParseTree::new(tree.children[0].root.to_string()));
         return transformed_tree;
     }
+    let original = head.is_none();
+    let markers = &binarisation.markers;
+    let root = if original {
+        markers.escape(tree.root).into_owned()
+    } else {
+        tree.root.to_string()
+    };
+    let mut transformed_tree = ParseTree::new(root.clone());
     if original {
-        transformed_tree.root += &add_parents(parents, vertical);
+        transformed_tree.root += &add_parents(parents, binarisation);
     } else {
         // when origin = false parents is at least one long because it is at least a child
-        transformed_tree.root += &add_parents(&parents[..parents.len() - 1], vertical);
+        transformed_tree.root += &add_parents(&parents[..parents.len() - 1], binarisation);
     }
 
     let mut parents = parents.to_owned();
@@ -57,70 +113,127 @@ fn binarise_tree(
         parents.push(root.clone());
     }
     if tree.children.len() > 2 {
-        let first = tree.children.remove(0);
-        let remaining = tree.children;
-        transformed_tree
-            .children
-            .push(binarise_tree(first, horizontal, vertical, &parents, true));
-        transformed_tree
-            .children
-            .push(squash_children(remaining, horizontal, vertical, &parents));
+        let head = head.unwrap_or_else(|| match binarisation.factorisation {
+            Factorisation::Head => binarisation.head_rules.find_head(&tree, markers),
+            Factorisation::Right | Factorisation::Left => 0,
+        });
+        let split_first = match binarisation.factorisation {
+            Factorisation::Right => true,
+            Factorisation::Left => false,
+            Factorisation::Head => head > 0,
+        };
+        if split_first {
+            let first = tree.children.remove(0);
+            let remaining = tree.children;
+            transformed_tree
+                .children
+                .push(binarise_tree(first, binarisation, &parents, None));
+            transformed_tree.children.push(squash_children(
+                remaining,
+                head.saturating_sub(1),
+                binarisation,
+                &parents,
+            ));
+        } else {
+            let last = tree.children.pop().unwrap();
+            let remaining = tree.children;
+            transformed_tree.children.push(squash_children(
+                remaining,
+                head,
+                binarisation,
+                &parents,
+            ));
+            transformed_tree
+                .children
+                .push(binarise_tree(last, binarisation, &parents, None));
+        }
     } else {
         for child in tree.children {
             transformed_tree
                 .children
-                .push(binarise_tree(child, horizontal, vertical, &parents, true));
+                .push(binarise_tree(child, binarisation, &parents, None));
         }
     }
 
     transformed_tree
 }
 
+/// returns the children whose labels are kept in an intermediate node: the last ones for
+/// right factorisation, the first ones for left factorisation and the ones next to the head
+/// for head-outward factorisation
+fn remembered(length: usize, head: usize, binarisation: &Binarisation) -> Range<usize> {
+    let horizontal = binarisation.horizontal.min(length as u64) as usize;
+    match binarisation.factorisation {
+        Factorisation::Right => length - horizontal..length,
+        Factorisation::Left => 0..horizontal,
+        Factorisation::Head => {
+            let (mut start, mut end) = (head, head + 1);
+            while end - start < horizontal {
+                if end < length {
+                    end += 1;
+                }
+                if end - start < horizontal && start > 0 {
+                    start -= 1;
+                }
+            }
+            start..end
+        }
+    }
+}
+
 fn squash_children(
     children: Vec<ParseTree<&str>>,
-    horizontal: u64,
-    vertical: u64,
+    head: usize,
+    binarisation: &Binarisation,
     parents: &[String],
 ) -> ParseTree<String> {
-    let start = max(children.len() as i64 - horizontal as i64, 0) as usize;
+    let markers = &binarisation.markers;
     let original = parents[parents.len() - 1].clone();
-    let mut node = original.clone() + "|<";
-    node += &children[start..]
+    let siblings: Vec<_> = children[remembered(children.len(), head, binarisation)]
         .iter()
-        .map(|e| e.root.to_string())
-        .reduce(|a, e| a + "," + &e)
-        .unwrap();
-    node += ">";
-    let mut tree = if horizontal == 1 {
+        .map(|e| markers.escape(e.root))
+        .collect();
+    let node = markers.format(&Annotation {
+        base: &original,
+        siblings: Some(siblings.iter().map(|e| e.as_ref()).collect()),
+        parents: None,
+    });
+    let mut tree = if binarisation.horizontal == 1 {
         ParseTree::new(&original[..])
     } else {
         ParseTree::new(&node[..])
     };
     tree.children = children;
-    binarise_tree(tree, horizontal, vertical, parents, false)
+    binarise_tree(tree, binarisation, parents, Some(head))
 }
 
-fn add_parents(parents: &[String], vertical: u64) -> String {
+fn add_parents(parents: &[String], binarisation: &Binarisation) -> String {
+    let vertical = binarisation.vertical;
     if parents.is_empty() || vertical == 1 {
         return "".into();
     }
     let start = max(parents.len() as i64 - vertical as i64 + 1, 0) as usize;
-    let mut node = "^<".to_string();
-
-    node += &parents[start..]
-        .iter()
-        .map(|e| e.to_owned())
-        .rev()
-        .reduce(|a, e| a + "," + &e)
-        .unwrap();
-    node += ">";
-    node
+    binarisation.markers.format(&Annotation {
+        base: "",
+        siblings: None,
+        parents: Some(parents[start..].iter().map(|e| &e[..]).rev().collect()),
+    })
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::induce::parse_tree::ParseTree;
+    use crate::induce::parse_tree::{ParseTree, element};
+
+    fn binarisation(horizontal: u64, vertical: u64, factorisation: Factorisation) -> Binarisation {
+        Binarisation {
+            horizontal,
+            vertical,
+            factorisation,
+            head_rules: HeadRules::collins(),
+            markers: Markers::default(),
+        }
+    }
 
     #[test]
     fn test_binarise_tree() {
@@ -187,7 +300,46 @@ mod test {
                 ],
             }],
         };
-        let correct = binarise_tree(test_tree, 999, 3, &[], true);
+        let correct = binarise_tree(
+            test_tree,
+            &binarisation(999, 3, Factorisation::Right),
+            &[],
+            None,
+        );
         assert_eq!(correct, expected_tree);
     }
+
+    #[test]
+    fn test_binarise_escaped_labels() {
+        let (_, tree) = element("(S|X (A a) (, ,) (B^C (D d) (E e)) (F f))").unwrap();
+        let binarised = binarise_tree(tree, &binarisation(3, 2, Factorisation::Right), &[], None);
+        assert_eq!(
+            binarised.to_string(),
+            r"(S\|X (A a) (S\|X|<\,,B\^C,F> (, ,) (S\|X|<B\^C,F> (B\^C^<S\|X> (D d) (E e)) (F f))))"
+        );
+    }
+
+    #[test]
+    fn test_factorisations() {
+        let (_, tree) = element("(VP (VB a) (NP b) (PP c) (ADVP d))").unwrap();
+        let left = binarise_tree(tree, &binarisation(2, 1, Factorisation::Left), &[], None);
+        assert_eq!(
+            left.to_string(),
+            "(VP (VP|<VB,NP> (VP|<VB,NP> (VB a) (NP b)) (PP c)) (ADVP d))"
+        );
+
+        // NN is the head of the NP
+        let (_, tree) = element("(NP (DT a) (JJ b) (NN c) (PP d) (SBAR e))").unwrap();
+        let head = binarise_tree(tree, &binarisation(1, 1, Factorisation::Head), &[], None);
+        assert_eq!(
+            head.to_string(),
+            "(NP (DT a) (NP (JJ b) (NP (NP (NN c) (PP d)) (SBAR e))))"
+        );
+        let (_, tree) = element("(NP (DT a) (JJ b) (NN c) (PP d) (SBAR e))").unwrap();
+        let head = binarise_tree(tree, &binarisation(2, 1, Factorisation::Head), &[], None);
+        assert_eq!(
+            head.to_string(),
+            "(NP (DT a) (NP|<NN,PP> (JJ b) (NP|<NN,PP> (NP|<NN,PP> (NN c) (PP d)) (SBAR e))))"
+        );
+    }
 }
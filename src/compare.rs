@@ -0,0 +1,269 @@
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::{
+    eval::{f_measure, parameters::Parameters, percent, read_lines, read_sentence, score},
+    induce::parse_tree::TreeText,
+    random::Random,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Significance {
+    /// paired bootstrap resampling of the sentences
+    Bootstrap,
+    /// approximate randomisation: swap the outputs of each sentence with probability 1/2
+    Randomisation,
+}
+
+/// parses the coverage of the confidence intervals, which must lie strictly between 0 and 1
+pub fn parse_confidence(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(confidence) if confidence > 0.0 && confidence < 1.0 => Ok(confidence),
+        Ok(_) => Err("the confidence must be between 0 and 1".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// the bracket counts of one system on one sentence
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counts {
+    pub matched: usize,
+    pub gold: usize,
+    pub test: usize,
+}
+
+impl std::ops::AddAssign for Counts {
+    fn add_assign(&mut self, other: Self) {
+        self.matched += other.matched;
+        self.gold += other.gold;
+        self.test += other.test;
+    }
+}
+
+impl Counts {
+    pub fn f_measure(&self) -> f64 {
+        f_measure(
+            percent(self.matched, self.gold),
+            percent(self.matched, self.test),
+        )
+    }
+}
+
+/// the F1 of both systems on the sentences given by their indices, which may repeat
+fn difference(pairs: &[(Counts, Counts)], indices: impl Iterator<Item = usize>) -> (f64, f64) {
+    let mut first = Counts::default();
+    let mut second = Counts::default();
+    for i in indices {
+        first += pairs[i].0;
+        second += pairs[i].1;
+    }
+    (first.f_measure(), second.f_measure())
+}
+
+/// returns the bounds of the central `confidence` interval of the sorted `values`
+fn interval(values: &[f64], confidence: f64) -> (f64, f64) {
+    let tail = (1.0 - confidence) / 2.0;
+    let index = |quantile: f64| {
+        ((quantile * values.len() as f64) as usize).min(values.len().saturating_sub(1))
+    };
+    (values[index(tail)], values[index(1.0 - tail)])
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Comparison {
+    pub first: f64,
+    pub second: f64,
+    pub p_value: f64,
+    /// confidence intervals of the F1 of both systems and of their difference, only for the
+    /// bootstrap
+    pub intervals: Option<[(f64, f64); 3]>,
+}
+
+/// paired bootstrap: the p-value is the fraction of resampled test sets on which the better
+/// system is not better
+pub fn bootstrap(
+    pairs: &[(Counts, Counts)],
+    samples: usize,
+    confidence: f64,
+    random: &mut Random,
+) -> Comparison {
+    let (first, second) = difference(pairs, 0..pairs.len());
+    let observed = first - second;
+    let mut firsts = Vec::with_capacity(samples);
+    let mut seconds = Vec::with_capacity(samples);
+    let mut differences = Vec::with_capacity(samples);
+    let mut failures = 0;
+    for _ in 0..samples {
+        let (a, b) = difference(pairs, (0..pairs.len()).map(|_| random.below(pairs.len())));
+        // without an observed difference no system is better on any sample
+        if observed == 0.0 || (a - b) * observed.signum() <= 0.0 {
+            failures += 1;
+        }
+        firsts.push(a);
+        seconds.push(b);
+        differences.push(a - b);
+    }
+    for values in [&mut firsts, &mut seconds, &mut differences] {
+        values.sort_by(f64::total_cmp);
+    }
+    Comparison {
+        first,
+        second,
+        p_value: failures as f64 / samples as f64,
+        intervals: Some([
+            interval(&firsts, confidence),
+            interval(&seconds, confidence),
+            interval(&differences, confidence),
+        ]),
+    }
+}
+
+/// approximate randomisation: the p-value is the fraction of random swaps with at least the
+/// observed absolute difference
+pub fn randomisation(pairs: &[(Counts, Counts)], samples: usize, random: &mut Random) -> Comparison {
+    let (first, second) = difference(pairs, 0..pairs.len());
+    let observed = (first - second).abs();
+    let mut shuffled = pairs.to_vec();
+    let mut at_least = 0;
+    for _ in 0..samples {
+        for (pair, original) in shuffled.iter_mut().zip(pairs) {
+            *pair = if random.next_f64() < 0.5 {
+                (original.1, original.0)
+            } else {
+                *original
+            };
+        }
+        let (a, b) = difference(&shuffled, 0..shuffled.len());
+        if (a - b).abs() >= observed - 1e-12 {
+            at_least += 1;
+        }
+    }
+    Comparison {
+        first,
+        second,
+        p_value: (at_least + 1) as f64 / (samples + 1) as f64,
+        intervals: None,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn compare(
+    gold: &Path,
+    first: &Path,
+    second: &Path,
+    parameters: &Option<PathBuf>,
+    method: Significance,
+    samples: usize,
+    confidence: f64,
+    seed: u64,
+) {
+    let parameters = parameters
+        .as_ref()
+        .map_or_else(Parameters::default, |path| Parameters::from_file(path));
+    let gold = read_lines(gold);
+    let first = read_lines(first);
+    let second = read_lines(second);
+    // a missing tree would pair the following sentences with the wrong gold trees
+    if first.len() != gold.len() || second.len() != gold.len() {
+        eprintln!(
+            "the gold file has {} trees but the outputs have {} and {}",
+            gold.len(),
+            first.len(),
+            second.len()
+        );
+        std::process::exit(1);
+    }
+
+    let counts = |gold: &TreeText, test: &TreeText| -> Option<Counts> {
+        let gold = read_sentence(gold, &parameters).ok()?;
+        let test = read_sentence(test, &parameters).ok()?;
+        let score = score(&gold, &test, &parameters).ok()?;
+        Some(Counts {
+            matched: score.matched(parameters.labeled),
+            gold: score.gold_brackets,
+            test: score.test_brackets,
+        })
+    };
+    let mut pairs = Vec::new();
+    let mut skipped = 0;
+    for ((gold, first), second) in gold.iter().zip(&first).zip(&second) {
+        match (counts(gold, first), counts(gold, second)) {
+            (Some(first), Some(second)) => pairs.push((first, second)),
+            _ => skipped += 1,
+        }
+    }
+    if pairs.is_empty() {
+        eprintln!("no sentence can be scored for both outputs");
+        std::process::exit(1);
+    }
+
+    let mut random = Random::new(seed);
+    let comparison = match method {
+        Significance::Bootstrap => bootstrap(&pairs, samples, confidence, &mut random),
+        Significance::Randomisation => randomisation(&pairs, samples, &mut random),
+    };
+    println!("Sentences                 = {:6}", pairs.len());
+    println!("Skipped sentences         = {:6}", skipped);
+    println!("FMeasure first            = {:6.2}", comparison.first);
+    println!("FMeasure second           = {:6.2}", comparison.second);
+    println!(
+        "Difference                = {:6.2}",
+        comparison.first - comparison.second
+    );
+    if let Some(intervals) = comparison.intervals {
+        for (name, (low, high)) in ["first", "second", "difference"].iter().zip(intervals) {
+            let label = format!("{:.0}% CI {}", confidence * 100.0, name);
+            println!("{:<25} = [{:.2}, {:.2}]", label, low, high);
+        }
+    }
+    println!("p-value                   = {:.4}", comparison.p_value);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pairs(first_matched: usize, second_matched: usize) -> Vec<(Counts, Counts)> {
+        (0..50)
+            .map(|i| {
+                let counts = |matched: usize| Counts {
+                    matched: if i < matched { 10 } else { 5 },
+                    gold: 10,
+                    test: 10,
+                };
+                (counts(first_matched), counts(second_matched))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bootstrap_test() {
+        let mut random = Random::new(1);
+        let comparison = bootstrap(&pairs(40, 10), 1000, 0.95, &mut random);
+        assert!(comparison.first > comparison.second);
+        assert!(comparison.p_value < 0.01);
+        let [first, _, difference] = comparison.intervals.unwrap();
+        assert!(first.0 <= comparison.first && comparison.first <= first.1);
+        assert!(difference.0 > 0.0);
+
+        let comparison = bootstrap(&pairs(25, 25), 1000, 0.95, &mut random);
+        assert_eq!(comparison.p_value, 1.0);
+    }
+
+    #[test]
+    fn parse_confidence_test() {
+        assert_eq!(parse_confidence("0.9"), Ok(0.9));
+        assert!(parse_confidence("0").is_err());
+        assert!(parse_confidence("1").is_err());
+        assert!(parse_confidence("1.5").is_err());
+        assert!(parse_confidence("x").is_err());
+    }
+
+    #[test]
+    fn randomisation_test() {
+        let mut random = Random::new(1);
+        assert!(randomisation(&pairs(40, 10), 1000, &mut random).p_value < 0.01);
+        assert!(randomisation(&pairs(26, 25), 1000, &mut random).p_value > 0.5);
+    }
+}
@@ -1,82 +1,67 @@
-use std::{io, process::exit};
+use std::{borrow::Cow, io};
 
-use crate::induce::parse_tree::{ParseTree, element};
+use crate::{
+    induce::parse_tree::{ParseTree, read_trees, restore_brackets},
+    markers::Markers,
+    output::{OutputFormat, format_tree},
+};
 
-pub fn debinarise() {
-    for (line_number, line) in io::stdin().lines().enumerate() {
-        let Ok(line) = line else { continue };
-        let transformed_line = debinarise_line(line);
-        if let Some(transformed_line) = transformed_line {
-            println!("{}", transformed_line);
-        } else {
-            eprintln!("error while transforming line {}", line_number + 1);
-            exit(1);
+pub fn debinarise(restore: bool, markers: &Markers, output_format: OutputFormat) {
+    for tree_text in read_trees(io::stdin().lock(), "stdin") {
+        let tree = tree_text.parse_or_exit("stdin");
+        let mut tree = debinarise_tree(tree, markers);
+        if restore {
+            restore_brackets(&mut tree);
         }
+        println!("{}", format_tree(&tree, output_format));
     }
 }
 
-fn debinarise_line(line: String) -> Option<String> {
-    let Ok((remaining, tree)) = element(&line) else {
-        return None;
-    };
-    if remaining.trim() != "" {
-        return None;
-    }
-
-    let transformed_tree = debinarise_tree(tree);
-
-    Some(transformed_tree.to_string())
+/// returns the original label of an intermediate node or `None` if it is none
+fn demarkovize<'a>(label: &'a str, markers: &Markers) -> Option<Cow<'a, str>> {
+    markers.intermediate(label)
 }
 
-/// returns the string without extras or `None` if it is already without
-fn demarkovize(string: &str) -> Option<&str> {
-    let without_parents = if string.find('^').is_some() {
-        string.split('^').next().unwrap()
-    } else {
-        string
-    };
-    if without_parents.find('|').is_some() {
-        // is always Some
-        string.split('|').next()
-    } else {
-        None
+/// labels that `binarise` cannot have produced are kept as they are
+pub fn debinarise_tree(tree: ParseTree<&str>, markers: &Markers) -> ParseTree<String> {
+    // preterminals are not annotated by binarise
+    if tree.children.len() == 1 && tree.children[0].is_leaf() {
+        let mut transformed_tree = ParseTree::new(tree.root.to_string());
+        transformed_tree
+            .children
+            .push(ParseTree::new(tree.children[0].root.to_string()));
+        return transformed_tree;
     }
-}
-
-fn debinarise_tree(tree: ParseTree<&str>) -> ParseTree<String> {
-    let root = if tree.root.find('^').is_some() {
-        tree.root.split('^').next().unwrap().to_string()
-    } else {
-        tree.root.to_string()
-    };
-    let root = if root.find('|').is_some() {
-        root.split('|').next().unwrap().to_string()
-    } else {
-        root
-    };
+    let root = markers.base(tree.root).into_owned();
     let mut transformed_tree = ParseTree::new(root);
     for child in tree.children {
-        let demarkovize = demarkovize(child.root);
-        if demarkovize == Some(&transformed_tree.root) {
-            for child in debinarise_tree_remove(child, &transformed_tree.root) {
+        let demarkovize = demarkovize(child.root, markers);
+        if demarkovize.as_deref() == Some(&transformed_tree.root) {
+            for child in debinarise_tree_remove(child, &transformed_tree.root, markers) {
                 transformed_tree.children.push(child);
             }
         } else {
-            transformed_tree.children.push(debinarise_tree(child));
+            transformed_tree
+                .children
+                .push(debinarise_tree(child, markers));
         }
     }
     transformed_tree
 }
 
-fn debinarise_tree_remove(tree: ParseTree<&str>, root: &str) -> Vec<ParseTree<String>> {
+fn debinarise_tree_remove(
+    tree: ParseTree<&str>,
+    root: &str,
+    markers: &Markers,
+) -> Vec<ParseTree<String>> {
     let mut children = vec![];
     for child in tree.children {
-        if demarkovize(child.root) == Some(root) {
-            for child in debinarise_tree_remove(child, root) {
+        if demarkovize(child.root, markers).as_deref() == Some(root) {
+            for child in debinarise_tree_remove(child, root, markers) {
                 children.push(child);
             }
         } else {
-            children.push(debinarise_tree(child));
+            children.push(debinarise_tree(child, markers));
         }
     }
     children
@@ -85,7 +70,7 @@ fn debinarise_tree_remove(tree: ParseTree<&str>, root: &str) -> Vec<ParseTree<St
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::induce::parse_tree::ParseTree;
+    use crate::induce::parse_tree::{ParseTree, element};
 
     #[test]
     fn test_debinarise_tree() {
@@ -152,7 +137,47 @@ mod test {
                 ],
             }],
         };
-        let correct = debinarise_tree(test_tree);
+        let correct = debinarise_tree(test_tree, &Markers::default());
         assert_eq!(correct, expected_tree);
     }
+
+    #[test]
+    fn test_debinarise_escaped_labels() {
+        let markers = Markers::default();
+        let binarised = r"(S\|X (A a) (S\|X|<\,,B\^C,F> (, ,) (S\|X|<B\^C,F> (B\^C^<S\|X> (D d) (E e)) (F f))))";
+        let (_, tree) = element(binarised).unwrap();
+        assert_eq!(
+            debinarise_tree(tree, &markers).to_string(),
+            "(S|X (A a) (, ,) (B^C (D d) (E e)) (F f))"
+        );
+
+        // labels that binarise cannot have produced are not stripped
+        let (_, tree) = element("(A|<B>C (X|Y (D d) (E e)) (F^G f))").unwrap();
+        assert_eq!(
+            debinarise_tree(tree, &markers).to_string(),
+            "(A|<B>C (X|Y (D d) (E e)) (F^G f))"
+        );
+
+        let markers = Markers::new("@", "#", ";", "!").unwrap();
+        let (_, tree) = element("(S (A a) (S@B;C!#<S! (B b) (C c)))").unwrap();
+        assert_eq!(
+            debinarise_tree(tree, &markers).to_string(),
+            "(S (A a) (B b) (C c))"
+        );
+    }
+
+    #[test]
+    fn test_debinarise_factorisations() {
+        let markers = Markers::default();
+        for binarised in [
+            "(VP (VP|<VB,NP> (VP|<VB,NP> (VB a) (NP b)) (PP c)) (ADVP d))",
+            "(VP (VB a) (VP|<VB,NP> (VP|<VB,NP> (NP b) (PP c)) (ADVP d)))",
+        ] {
+            let (_, tree) = element(binarised).unwrap();
+            assert_eq!(
+                debinarise_tree(tree, &markers).to_string(),
+                "(VP (VB a) (NP b) (PP c) (ADVP d))"
+            );
+        }
+    }
 }
@@ -0,0 +1,625 @@
+use std::{io, path::Path, process::exit, thread};
+
+use foldhash::{HashMap, HashMapExt};
+
+use crate::{
+    induce::{self, grammar_locations, write_grammar},
+    parse::{
+        parse_rules, rule::Rhs, string_lookup::StringLookup, transform_sentence, weight_map::Item,
+    },
+    smoothing::SignatureScheme,
+};
+
+/// longest chain of unary rules that is followed when summing over derivations
+const MAX_UNARY_CHAIN: usize = 20;
+
+#[allow(clippy::too_many_arguments)]
+pub fn train_em(
+    rules: &Path,
+    lexicon: &Path,
+    grammar: &Option<String>,
+    initial_nonterminal: &str,
+    iterations: u64,
+    unking: &bool,
+    smoothing: Option<&SignatureScheme>,
+) {
+    let mut string_lookup = StringLookup::default();
+    let mut rule_lookup = HashMap::new();
+    let mut all_rules = HashMap::new();
+    parse_rules(
+        &mut string_lookup,
+        &mut rule_lookup,
+        &mut all_rules,
+        rules,
+        true,
+    );
+    parse_rules(
+        &mut string_lookup,
+        &mut rule_lookup,
+        &mut all_rules,
+        lexicon,
+        false,
+    );
+    let Some(initial_nonterminal) = string_lookup.get(initial_nonterminal) else {
+        eprintln!("initial nonterminal is not in the rules");
+        exit(1);
+    };
+    let mut em_grammar = EmGrammar::new(&all_rules, Item::NonTerminal(initial_nonterminal as u32));
+
+    let mut sentences = Vec::new();
+    let mut skipped = 0;
+    for line in io::stdin().lines().map_while(Result::ok) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match transform_sentence(&line, &string_lookup, unking, smoothing, &[], false) {
+            Some(sentence) => sentences.push(sentence),
+            None => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        eprintln!("skipping {skipped} sentences with words that are not in the lexicon");
+    }
+
+    for iteration in 1..=iterations {
+        let expectation = em_grammar.expectation(&sentences);
+        eprintln!(
+            "iteration {}: log-likelihood {} ({} sentences without parse)",
+            iteration, expectation.log_likelihood, expectation.unparsable
+        );
+        em_grammar.maximize(&expectation.counts);
+    }
+
+    let (mut rules, mut lexicon, mut words) = grammar_locations(grammar);
+    write_grammar(
+        &mut rules,
+        &mut lexicon,
+        &mut words,
+        &em_grammar.to_grammar(&string_lookup),
+    );
+}
+
+/// a grammar over densely numbered non-terminals for computing inside and outside weights
+pub struct EmGrammar {
+    /// the item of each dense non-terminal index
+    non_terminals: Vec<Item>,
+    start: usize,
+    rules: Vec<EmRule>,
+    weights: Vec<f64>,
+    /// indices of binary rules by their left child
+    binary_by_left: Vec<Vec<usize>>,
+    /// indices of binary rules by their left hand side
+    binary_by_lhs: Vec<Vec<usize>>,
+    /// indices of unary rules with non-terminals on the right
+    unary: Vec<usize>,
+    /// indices of lexical rules by their terminal
+    lexical: HashMap<Item, Vec<usize>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EmRule {
+    Binary(usize, usize, usize),
+    Unary(usize, usize),
+    Lexical(usize, Item),
+}
+
+impl EmRule {
+    fn lhs(&self) -> usize {
+        match *self {
+            EmRule::Binary(lhs, _, _) | EmRule::Unary(lhs, _) | EmRule::Lexical(lhs, _) => lhs,
+        }
+    }
+}
+
+/// the result of the expectation step over a corpus
+pub struct Expectation {
+    /// the expected count of each rule
+    pub counts: Vec<f64>,
+    pub log_likelihood: f64,
+    /// number of sentences without any derivation
+    pub unparsable: u64,
+}
+
+impl EmGrammar {
+    pub fn new(all_rules: &HashMap<Item, HashMap<Rhs<Item>, f64>>, start: Item) -> Self {
+        let mut index: HashMap<Item, usize> = HashMap::new();
+        let mut non_terminals = Vec::new();
+        let mut dense = |item: Item| {
+            *index.entry(item).or_insert_with(|| {
+                non_terminals.push(item);
+                non_terminals.len() - 1
+            })
+        };
+        let start = dense(start);
+        let mut rules = Vec::new();
+        let mut weights = Vec::new();
+        let mut sorted_rules: Vec<(&Item, &HashMap<Rhs<Item>, f64>)> = all_rules.iter().collect();
+        sorted_rules.sort_by_key(|(lhs, _)| **lhs);
+        for (lhs, bodies) in sorted_rules {
+            let mut bodies: Vec<(&Rhs<Item>, &f64)> = bodies.iter().collect();
+            bodies.sort_by_key(|(rhs, _)| **rhs);
+            for (rhs, weight) in bodies {
+                let lhs = dense(*lhs);
+                let rule = match *rhs {
+                    Rhs::Binary(left, right) => EmRule::Binary(lhs, dense(left), dense(right)),
+                    Rhs::Unary(item @ Item::NonTerminal(_)) => EmRule::Unary(lhs, dense(item)),
+                    Rhs::Unary(item @ Item::Terminal(_)) => EmRule::Lexical(lhs, item),
+                };
+                rules.push(rule);
+                weights.push(*weight);
+            }
+        }
+        let mut grammar = Self {
+            binary_by_left: vec![vec![]; non_terminals.len()],
+            binary_by_lhs: vec![vec![]; non_terminals.len()],
+            non_terminals,
+            start,
+            rules,
+            weights,
+            unary: vec![],
+            lexical: HashMap::new(),
+        };
+        for (index, rule) in grammar.rules.iter().enumerate() {
+            match *rule {
+                EmRule::Binary(lhs, left, _) => {
+                    grammar.binary_by_left[left].push(index);
+                    grammar.binary_by_lhs[lhs].push(index);
+                }
+                EmRule::Unary(_, _) => grammar.unary.push(index),
+                EmRule::Lexical(_, terminal) => {
+                    grammar.lexical.entry(terminal).or_default().push(index)
+                }
+            }
+        }
+        grammar
+    }
+
+    /// computes the expected rule counts over all `sentences` in parallel
+    pub fn expectation(&self, sentences: &[Vec<Item>]) -> Expectation {
+        let workers = thread::available_parallelism()
+            .map(usize::from)
+            .unwrap_or(1)
+            .min(sentences.len())
+            .max(1);
+        let chunk_size = sentences.len().div_ceil(workers).max(1);
+        thread::scope(|scope| {
+            let handles: Vec<_> = sentences
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        let mut expectation = Expectation {
+                            counts: vec![0.0; self.rules.len()],
+                            log_likelihood: 0.0,
+                            unparsable: 0,
+                        };
+                        for sentence in chunk {
+                            match self.expected_counts(sentence, &mut expectation.counts) {
+                                Some(log_probability) => {
+                                    expectation.log_likelihood += log_probability
+                                }
+                                None => expectation.unparsable += 1,
+                            }
+                        }
+                        expectation
+                    })
+                })
+                .collect();
+            let mut total = Expectation {
+                counts: vec![0.0; self.rules.len()],
+                log_likelihood: 0.0,
+                unparsable: 0,
+            };
+            for handle in handles {
+                let expectation = handle.join().expect("expectation thread panicked");
+                for (total, count) in total.counts.iter_mut().zip(expectation.counts) {
+                    *total += count;
+                }
+                total.log_likelihood += expectation.log_likelihood;
+                total.unparsable += expectation.unparsable;
+            }
+            total
+        })
+    }
+
+    /// sets the weight of each rule to its relative expected count among the rules with the same
+    /// left hand side, non-terminals without any expected count keep their weights
+    pub fn maximize(&mut self, counts: &[f64]) {
+        let mut totals = vec![0.0; self.non_terminals.len()];
+        for (rule, count) in self.rules.iter().zip(counts) {
+            totals[rule.lhs()] += count;
+        }
+        for ((rule, weight), count) in self.rules.iter().zip(&mut self.weights).zip(counts) {
+            let total = totals[rule.lhs()];
+            if total > 0.0 {
+                *weight = count / total;
+            }
+        }
+    }
+
+    /// returns the rules with their current weights in the form written by `induce`
+    ///
+    /// rules without weight are left out, so that words that only occur in them are not written
+    /// to the `.words` file and are replaced as unknown words when parsing
+    pub fn to_grammar(
+        &self,
+        string_lookup: &StringLookup,
+    ) -> HashMap<String, HashMap<induce::Rhs, f64>> {
+        let name = |item: Item| {
+            string_lookup
+                .get_string(usize::from(item))
+                .expect("every item is in the lookup")
+                .clone()
+        };
+        let mut grammar: HashMap<String, HashMap<induce::Rhs, f64>> = HashMap::new();
+        for (rule, weight) in self.rules.iter().zip(&self.weights) {
+            if *weight == 0.0 {
+                continue;
+            }
+            let rhs = match *rule {
+                EmRule::Binary(_, left, right) => induce::Rhs::NonTerminals(vec![
+                    name(self.non_terminals[left]),
+                    name(self.non_terminals[right]),
+                ]),
+                EmRule::Unary(_, child) => {
+                    induce::Rhs::NonTerminals(vec![name(self.non_terminals[child])])
+                }
+                EmRule::Lexical(_, terminal) => induce::Rhs::Terminal(name(terminal)),
+            };
+            grammar
+                .entry(name(self.non_terminals[rule.lhs()]))
+                .or_default()
+                .insert(rhs, *weight);
+        }
+        grammar
+    }
+
+    fn chart_index(&self, length: usize, start: usize, end: usize) -> usize {
+        (start * (length + 1) + end) * self.non_terminals.len()
+    }
+
+    /// adds the weights of all chains of unary rules to the weights of one span, `transposed`
+    /// follows the rules from the right to the left hand side as needed for outside weights
+    fn unary_closure(&self, weights: &mut [f64], transposed: bool) {
+        let mut delta = weights.to_vec();
+        for _ in 0..MAX_UNARY_CHAIN {
+            let mut next = vec![0.0; weights.len()];
+            let mut changed = false;
+            for &index in &self.unary {
+                let EmRule::Unary(lhs, child) = self.rules[index] else {
+                    unreachable!()
+                };
+                let (from, to) = if transposed {
+                    (lhs, child)
+                } else {
+                    (child, lhs)
+                };
+                if delta[from] > 0.0 {
+                    next[to] += self.weights[index] * delta[from];
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+            for (weight, added) in weights.iter_mut().zip(&next) {
+                *weight += added;
+            }
+            delta = next;
+        }
+    }
+
+    fn span_index(length: usize, start: usize, end: usize) -> usize {
+        start * (length + 1) + end
+    }
+
+    /// computes the inside weights of all spans of the `sentence`
+    ///
+    /// the weights of each span are normalised to sum to 1, their logarithmic scale is returned
+    /// beside them, so that long sentences do not underflow. spans without weight have the scale
+    /// `-inf`
+    pub fn inside(&self, sentence: &[Item]) -> (Vec<f64>, Vec<f64>) {
+        let length = sentence.len();
+        let size = self.non_terminals.len();
+        let mut inside = vec![0.0; (length + 1) * (length + 1) * size];
+        let mut scales = vec![f64::NEG_INFINITY; (length + 1) * (length + 1)];
+        for width in 1..=length {
+            for (start, word) in sentence.iter().enumerate().take(length - width + 1) {
+                let end = start + width;
+                let mut weights = vec![0.0; size];
+                let mut scale = 0.0;
+                if width == 1 {
+                    for &index in self.lexical.get(word).into_iter().flatten() {
+                        weights[self.rules[index].lhs()] += self.weights[index];
+                    }
+                } else {
+                    // the splits are combined relative to the largest scale
+                    let split_scale = |split| {
+                        scales[Self::span_index(length, start, split)]
+                            + scales[Self::span_index(length, split, end)]
+                    };
+                    scale = (start + 1..end)
+                        .map(split_scale)
+                        .fold(f64::NEG_INFINITY, f64::max);
+                    if scale == f64::NEG_INFINITY {
+                        continue;
+                    }
+                    for split in start + 1..end {
+                        let factor = (split_scale(split) - scale).exp();
+                        if factor == 0.0 {
+                            continue;
+                        }
+                        let left = self.chart_index(length, start, split);
+                        let right = self.chart_index(length, split, end);
+                        for (child, rules) in self.binary_by_left.iter().enumerate() {
+                            let left_weight = inside[left + child];
+                            if left_weight == 0.0 {
+                                continue;
+                            }
+                            for &index in rules {
+                                let EmRule::Binary(lhs, _, right_child) = self.rules[index] else {
+                                    unreachable!()
+                                };
+                                let right_weight = inside[right + right_child];
+                                if right_weight > 0.0 {
+                                    weights[lhs] +=
+                                        self.weights[index] * left_weight * right_weight * factor;
+                                }
+                            }
+                        }
+                    }
+                }
+                self.unary_closure(&mut weights, false);
+                let total: f64 = weights.iter().sum();
+                if total <= 0.0 {
+                    continue;
+                }
+                for weight in &mut weights {
+                    *weight /= total;
+                }
+                let span = self.chart_index(length, start, end);
+                inside[span..span + size].copy_from_slice(&weights);
+                scales[Self::span_index(length, start, end)] = scale + total.ln();
+            }
+        }
+        (inside, scales)
+    }
+
+    /// adds the expected rule counts of one sentence to `counts` and returns the log-probability
+    /// of the sentence or `None` if it has no derivation
+    pub fn expected_counts(&self, sentence: &[Item], counts: &mut [f64]) -> Option<f64> {
+        let length = sentence.len();
+        if length == 0 {
+            return None;
+        }
+        let size = self.non_terminals.len();
+        let (inside, inside_scales) = self.inside(sentence);
+        let root = self.chart_index(length, 0, length);
+        if inside[root + self.start] <= 0.0 {
+            return None;
+        }
+        let log_probability =
+            inside[root + self.start].ln() + inside_scales[Self::span_index(length, 0, length)];
+        // outside weights before following unary rules, scaled like the inside weights
+        let mut outside = vec![0.0; inside.len()];
+        let mut outside_scales = vec![f64::NEG_INFINITY; inside_scales.len()];
+        outside[root + self.start] = 1.0;
+        outside_scales[Self::span_index(length, 0, length)] = 0.0;
+        // adds `weight` with the logarithmic scale `scale` to the outside weight of `item`
+        let add = |outside: &mut [f64],
+                   outside_scales: &mut [f64],
+                   (start, end): (usize, usize),
+                   item: usize,
+                   weight: f64,
+                   scale: f64| {
+            let span = self.chart_index(length, start, end);
+            let current = &mut outside_scales[Self::span_index(length, start, end)];
+            if scale > *current {
+                let factor = (*current - scale).exp();
+                for weight in &mut outside[span..span + size] {
+                    *weight *= factor;
+                }
+                *current = scale;
+            }
+            outside[span + item] += weight * (scale - *current).exp();
+        };
+        for width in (1..=length).rev() {
+            for (start, word) in sentence.iter().enumerate().take(length - width + 1) {
+                let end = start + width;
+                let span = self.chart_index(length, start, end);
+                let mut weights = outside[span..span + size].to_vec();
+                self.unary_closure(&mut weights, true);
+                let total: f64 = weights.iter().sum();
+                if total <= 0.0 {
+                    continue;
+                }
+                for weight in &mut weights {
+                    *weight /= total;
+                }
+                let scale = outside_scales[Self::span_index(length, start, end)] + total.ln();
+                let inside_scale = inside_scales[Self::span_index(length, start, end)];
+                let factor = (scale + inside_scale - log_probability).exp();
+                for &index in &self.unary {
+                    let EmRule::Unary(lhs, child) = self.rules[index] else {
+                        unreachable!()
+                    };
+                    counts[index] +=
+                        weights[lhs] * self.weights[index] * inside[span + child] * factor;
+                }
+                if width == 1 {
+                    let factor = (scale - log_probability).exp();
+                    for &index in self.lexical.get(word).into_iter().flatten() {
+                        let lhs = self.rules[index].lhs();
+                        counts[index] += weights[lhs] * self.weights[index] * factor;
+                    }
+                }
+                for (lhs, rules) in self.binary_by_lhs.iter().enumerate() {
+                    let parent = weights[lhs];
+                    if parent == 0.0 {
+                        continue;
+                    }
+                    for &index in rules {
+                        let EmRule::Binary(_, left_child, right_child) = self.rules[index] else {
+                            unreachable!()
+                        };
+                        let weight = parent * self.weights[index];
+                        for split in start + 1..end {
+                            let left = self.chart_index(length, start, split);
+                            let right = self.chart_index(length, split, end);
+                            let left_inside = inside[left + left_child];
+                            let right_inside = inside[right + right_child];
+                            if left_inside == 0.0 || right_inside == 0.0 {
+                                continue;
+                            }
+                            let left_scale = inside_scales[Self::span_index(length, start, split)];
+                            let right_scale = inside_scales[Self::span_index(length, split, end)];
+                            counts[index] += weight
+                                * left_inside
+                                * right_inside
+                                * (scale + left_scale + right_scale - log_probability).exp();
+                            add(
+                                &mut outside,
+                                &mut outside_scales,
+                                (start, split),
+                                left_child,
+                                weight * right_inside,
+                                scale + right_scale,
+                            );
+                            add(
+                                &mut outside,
+                                &mut outside_scales,
+                                (split, end),
+                                right_child,
+                                weight * left_inside,
+                                scale + left_scale,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        Some(log_probability)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::insert_into_lookup;
+
+    fn grammar(rules: &[&str], lexicon: &[&str]) -> (StringLookup, EmGrammar) {
+        let mut string_map = StringLookup::default();
+        let mut rule_lookup = HashMap::new();
+        let mut all_rules = HashMap::new();
+        for line in lexicon {
+            insert_into_lookup(
+                &mut string_map,
+                false,
+                &mut rule_lookup,
+                &mut all_rules,
+                line.to_string(),
+            );
+        }
+        for line in rules {
+            insert_into_lookup(
+                &mut string_map,
+                true,
+                &mut rule_lookup,
+                &mut all_rules,
+                line.to_string(),
+            );
+        }
+        let start = Item::NonTerminal(string_map.get("ROOT").unwrap() as u32);
+        (string_map, EmGrammar::new(&all_rules, start))
+    }
+
+    #[test]
+    fn inside_test() {
+        let (string_map, grammar) = grammar(
+            &["ROOT -> A A 0.5", "ROOT -> A 0.5", "A -> A A 0.2"],
+            &["A a 0.8"],
+        );
+        let sentence = transform_sentence("a a a", &string_map, &false, None, &[], false).unwrap();
+        let (inside, scales) = grammar.inside(&sentence);
+        // A over two words: 0.2 * 0.8 * 0.8, A over three words: both bracketings
+        let a_two = 0.2 * 0.8 * 0.8;
+        let a_three = 2.0 * 0.2 * 0.8 * a_two;
+        let root_three = 0.5 * a_three + 2.0 * 0.5 * 0.8 * a_two;
+        let probability = inside[grammar.chart_index(3, 0, 3) + grammar.start]
+            * scales[EmGrammar::span_index(3, 0, 3)].exp();
+        assert!((probability - root_three).abs() < 1e-12);
+    }
+
+    #[test]
+    fn expected_counts_test() {
+        let (string_map, grammar) =
+            grammar(&["ROOT -> A B 1", "A -> B 0.5"], &["A a 0.5", "B a 1"]);
+        let sentence = transform_sentence("a a", &string_map, &false, None, &[], false).unwrap();
+        let mut counts = vec![0.0; grammar.rules.len()];
+        let log_probability = grammar.expected_counts(&sentence, &mut counts).unwrap();
+        assert!(log_probability.abs() < 1e-12);
+        let dense = |name: &str| {
+            let item = Item::NonTerminal(string_map.get(name).unwrap() as u32);
+            grammar
+                .non_terminals
+                .iter()
+                .position(|e| *e == item)
+                .unwrap()
+        };
+        let count = |rule: EmRule| counts[grammar.rules.iter().position(|e| *e == rule).unwrap()];
+        // the first word is A directly or A -> B, each with half the probability
+        assert!((count(EmRule::Unary(dense("A"), dense("B"))) - 0.5).abs() < 1e-12);
+        assert!((count(EmRule::Binary(dense("ROOT"), dense("A"), dense("B"))) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn underflow_test() {
+        let (string_map, grammar) =
+            grammar(&["ROOT -> A 1", "A -> A B 1"], &["A a 0.001", "B a 0.001"]);
+        // the only derivation has the probability 0.001^120, which is below the smallest f64
+        let line = vec!["a"; 120].join(" ");
+        let sentence = transform_sentence(&line, &string_map, &false, None, &[], false).unwrap();
+        let mut counts = vec![0.0; grammar.rules.len()];
+        let log_probability = grammar.expected_counts(&sentence, &mut counts).unwrap();
+        assert!((log_probability - 120.0 * 0.001f64.ln()).abs() < 1e-9);
+        let dense = |name: &str| {
+            let item = Item::NonTerminal(string_map.get(name).unwrap() as u32);
+            grammar
+                .non_terminals
+                .iter()
+                .position(|e| *e == item)
+                .unwrap()
+        };
+        let count = |rule: EmRule| counts[grammar.rules.iter().position(|e| *e == rule).unwrap()];
+        assert!((count(EmRule::Binary(dense("A"), dense("A"), dense("B"))) - 119.0).abs() < 1e-9);
+        assert!((count(EmRule::Unary(dense("ROOT"), dense("A"))) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn maximize_test() {
+        let (string_map, mut grammar) = grammar(&["ROOT -> A A 0.5", "ROOT -> A 0.5"], &["A a 1"]);
+        let sentences = vec![
+            transform_sentence("a a", &string_map, &false, None, &[], false).unwrap(),
+            transform_sentence("a", &string_map, &false, None, &[], false).unwrap(),
+            transform_sentence("a", &string_map, &false, None, &[], false).unwrap(),
+        ];
+        let expectation = grammar.expectation(&sentences);
+        assert!((expectation.log_likelihood - 3.0 * 0.5f64.ln()).abs() < 1e-12);
+        grammar.maximize(&expectation.counts);
+        let trained = grammar.to_grammar(&string_map);
+        let binary = induce::Rhs::NonTerminals(vec!["A".to_string(), "A".to_string()]);
+        assert!((trained["ROOT"][&binary] - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn unseen_word_test() {
+        let (string_map, mut grammar) = grammar(&["ROOT -> A 1"], &["A a 0.5", "A b 0.5"]);
+        let sentences =
+            vec![transform_sentence("a", &string_map, &false, None, &[], false).unwrap()];
+        let expectation = grammar.expectation(&sentences);
+        grammar.maximize(&expectation.counts);
+        let trained = grammar.to_grammar(&string_map);
+        assert_eq!(trained["A"][&induce::Rhs::Terminal("a".to_string())], 1.0);
+        // b is not in the training sentences, its rule has no weight and is not written
+        assert!(!trained["A"].contains_key(&induce::Rhs::Terminal("b".to_string())));
+    }
+}
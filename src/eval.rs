@@ -0,0 +1,541 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::Path,
+    process::exit,
+};
+
+use foldhash::{HashMap, HashMapExt};
+
+use crate::induce::parse_tree::{ParseTree, TreeText, read_trees, sentence_tokens};
+use crate::json::Json;
+use breakdown::{Breakdown, BreakdownFormat};
+use parameters::Parameters;
+
+pub mod breakdown;
+pub mod parameters;
+
+pub fn eval(
+    gold: &Path,
+    predicted: &Path,
+    parameters: &Option<std::path::PathBuf>,
+    breakdown_format: Option<BreakdownFormat>,
+) {
+    let parameters = parameters
+        .as_ref()
+        .map_or_else(Parameters::default, |path| Parameters::from_file(path));
+    let gold_lines = read_lines(gold);
+    let predicted_lines = read_lines(predicted);
+    if gold_lines.len() != predicted_lines.len() {
+        eprintln!(
+            "the gold file has {} trees but the predicted file has {}",
+            gold_lines.len(),
+            predicted_lines.len()
+        );
+    }
+    let json = breakdown_format == Some(BreakdownFormat::Json);
+
+    let mut all = Summary::default();
+    let mut short = Summary::default();
+    let mut breakdown = Breakdown::default();
+    if !json {
+        print_header();
+    }
+    for (index, (gold_line, predicted_line)) in gold_lines.iter().zip(&predicted_lines).enumerate()
+    {
+        let sentences = read_sentence(gold_line, &parameters).and_then(|gold| {
+            read_sentence(predicted_line, &parameters).map(|predicted| (gold, predicted))
+        });
+        let score = sentences
+            .as_ref()
+            .map_err(Clone::clone)
+            .and_then(|(gold, predicted)| score(gold, predicted, &parameters));
+        if let (Ok((gold, predicted)), Ok(score)) = (&sentences, &score) {
+            breakdown.add(gold, predicted, score);
+        }
+        if !json {
+            print_sentence(index + 1, &score, &parameters);
+        }
+        all.add(&score);
+        if score
+            .as_ref()
+            .is_ok_and(|e| e.length <= parameters.cutoff_length)
+        {
+            short.add(&score);
+        }
+    }
+    if json {
+        let document = Json::object([
+            ("all", all.to_json()),
+            (
+                "cutoff",
+                Json::object([
+                    ("length", parameters.cutoff_length.into()),
+                    ("summary", short.to_json()),
+                ]),
+            ),
+            ("breakdown", breakdown.to_json()),
+        ]);
+        println!("{}", document);
+        return;
+    }
+    println!();
+    println!("=== Summary ===");
+    println!();
+    println!("-- All --");
+    all.print();
+    println!();
+    println!("-- len<={} --", parameters.cutoff_length);
+    short.print();
+    if breakdown_format == Some(BreakdownFormat::Text) {
+        breakdown.print();
+    }
+}
+
+/// reads the trees of the file as text, a tree may be spread over several lines
+pub fn read_lines(path: &Path) -> Vec<TreeText> {
+    let Ok(file) = File::open(path) else {
+        eprintln!("cannot open {}", path.display());
+        exit(1);
+    };
+    read_trees(BufReader::new(file), &path.display().to_string()).collect()
+}
+
+/// a labelled span over the words `start..end` of a sentence
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Bracket {
+    pub label: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Bracket {
+    /// returns true if the brackets overlap without one containing the other
+    pub fn crosses(&self, other: &Bracket) -> bool {
+        (self.start < other.start && other.start < self.end && self.end < other.end)
+            || (other.start < self.start && self.start < other.end && other.end < self.end)
+    }
+}
+
+/// the words, tags and brackets of a tree as evalb compares them
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Sentence {
+    pub words: Vec<String>,
+    /// the tag of each word, words directly below a bracket have no tag
+    pub tags: Vec<Option<String>>,
+    pub brackets: Vec<Bracket>,
+    /// the number of words that count for the sentence length
+    pub length: usize,
+}
+
+/// reads a tree or a `(NOPARSE w1 w2 …)` line of the parser
+///
+/// a failed parse is one bracket `NOPARSE` over the sentence without any tags, as evalb reads it.
+/// the unlabelled root of the Penn Treebank becomes `ROOT` like in all other subcommands
+pub fn read_sentence(tree_text: &TreeText, parameters: &Parameters) -> Result<Sentence, String> {
+    if let Some(words) = tree_text
+        .text
+        .trim()
+        .strip_prefix("(NOPARSE")
+        .and_then(|e| e.strip_suffix(')'))
+    {
+        let mut sentence = Sentence::default();
+        for word in sentence_tokens(words) {
+            sentence.words.push(word);
+            sentence.tags.push(None);
+        }
+        sentence.length = sentence.words.len();
+        if !sentence.words.is_empty() && !parameters.delete_labels.contains("NOPARSE") {
+            sentence.brackets.push(Bracket {
+                label: "NOPARSE".to_string(),
+                start: 0,
+                end: sentence.words.len(),
+            });
+        }
+        return Ok(sentence);
+    }
+    match tree_text.parse() {
+        Ok(tree) => Ok(sentence(&tree, parameters)),
+        Err(_) => Err("cannot read the tree".to_string()),
+    }
+}
+
+/// extracts the words, tags and brackets of the `tree`
+///
+/// words whose tag is a deleted label are removed and brackets which are deleted or span no word
+/// are left out
+pub fn sentence<T: AsRef<str>>(tree: &ParseTree<T>, parameters: &Parameters) -> Sentence {
+    let mut sentence = Sentence::default();
+    collect(tree, parameters, &mut sentence);
+    sentence
+}
+
+fn collect<T: AsRef<str>>(tree: &ParseTree<T>, parameters: &Parameters, sentence: &mut Sentence) {
+    let label = tree.root.as_ref();
+    if tree.is_leaf() {
+        sentence.words.push(label.to_string());
+        sentence.tags.push(None);
+        sentence.length += 1;
+        return;
+    }
+    if tree.children.len() == 1 && tree.children[0].is_leaf() {
+        if parameters.delete_labels.contains(label) {
+            return;
+        }
+        sentence.words.push(tree.children[0].root.as_ref().to_string());
+        sentence.tags.push(Some(label.to_string()));
+        if !parameters.delete_labels_for_length.contains(label) {
+            sentence.length += 1;
+        }
+        return;
+    }
+    let start = sentence.words.len();
+    for child in &tree.children {
+        collect(child, parameters, sentence);
+    }
+    let end = sentence.words.len();
+    let label = parameters.label(label);
+    if end > start && !parameters.delete_labels.contains(label) {
+        sentence.brackets.push(Bracket {
+            label: label.to_string(),
+            start,
+            end,
+        });
+    }
+}
+
+/// the comparison of a predicted sentence with the gold sentence
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Score {
+    pub length: usize,
+    pub labeled_matched: usize,
+    pub unlabeled_matched: usize,
+    pub gold_brackets: usize,
+    pub test_brackets: usize,
+    /// number of predicted brackets that cross a gold bracket
+    pub crossing: usize,
+    pub words: usize,
+    pub correct_tags: usize,
+}
+
+impl Score {
+    pub fn matched(&self, labeled: bool) -> usize {
+        if labeled {
+            self.labeled_matched
+        } else {
+            self.unlabeled_matched
+        }
+    }
+}
+
+/// returns the number of brackets in both lists, each bracket is matched at most once
+pub fn matching_brackets<K: std::hash::Hash + Eq>(
+    gold: impl Iterator<Item = K>,
+    test: impl Iterator<Item = K>,
+) -> usize {
+    let mut remaining: HashMap<K, usize> = HashMap::new();
+    for bracket in gold {
+        *remaining.entry(bracket).or_default() += 1;
+    }
+    let mut matched = 0;
+    for bracket in test {
+        if let Some(count) = remaining.get_mut(&bracket).filter(|e| **e > 0) {
+            *count -= 1;
+            matched += 1;
+        }
+    }
+    matched
+}
+
+/// compares the `test` sentence with the `gold` sentence, sentences with different words are
+/// an error
+pub fn score(gold: &Sentence, test: &Sentence, parameters: &Parameters) -> Result<Score, String> {
+    if gold.words.len() != test.words.len() {
+        return Err("Length unmatch".to_string());
+    }
+    if gold
+        .words
+        .iter()
+        .zip(&test.words)
+        .any(|(gold, test)| parameters.word(gold) != parameters.word(test))
+    {
+        return Err("Words unmatch".to_string());
+    }
+    let correct_tags = gold
+        .tags
+        .iter()
+        .zip(&test.tags)
+        .filter(|(gold, test)| match (gold, test) {
+            (Some(gold), Some(test)) => parameters.label(gold) == parameters.label(test),
+            _ => false,
+        })
+        .count();
+    let crossing = test
+        .brackets
+        .iter()
+        .filter(|test| gold.brackets.iter().any(|gold| test.crosses(gold)))
+        .count();
+    Ok(Score {
+        length: gold.length,
+        labeled_matched: matching_brackets(gold.brackets.iter(), test.brackets.iter()),
+        unlabeled_matched: matching_brackets(
+            gold.brackets.iter().map(|e| (e.start, e.end)),
+            test.brackets.iter().map(|e| (e.start, e.end)),
+        ),
+        gold_brackets: gold.brackets.len(),
+        test_brackets: test.brackets.len(),
+        crossing,
+        words: gold.words.len(),
+        correct_tags,
+    })
+}
+
+pub fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * part as f64 / total as f64
+    }
+}
+
+pub fn f_measure(recall: f64, precision: f64) -> f64 {
+    if recall + precision == 0.0 {
+        0.0
+    } else {
+        2.0 * recall * precision / (recall + precision)
+    }
+}
+
+fn print_header() {
+    println!("  Sent.                        Matched  Bracket   Cross        Correct Tag");
+    println!(" ID  Len.  Stat. Recal  Prec.  Bracket gold test Bracket Words  Tags Accracy");
+    println!("============================================================================");
+}
+
+fn print_sentence(id: usize, score: &Result<Score, String>, parameters: &Parameters) {
+    match score {
+        Ok(score) => {
+            let matched = score.matched(parameters.labeled);
+            println!(
+                "{:4} {:4} {:4} {:6.2} {:6.2} {:6} {:6} {:5} {:5} {:6} {:5} {:6.2}",
+                id,
+                score.length,
+                0,
+                percent(matched, score.gold_brackets),
+                percent(matched, score.test_brackets),
+                matched,
+                score.gold_brackets,
+                score.test_brackets,
+                score.crossing,
+                score.words,
+                score.correct_tags,
+                percent(score.correct_tags, score.words),
+            );
+        }
+        Err(e) => println!("{:4} {:>4} {:4} {}", id, "-", 1, e),
+    }
+}
+
+/// the totals of all sentences
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub sentences: usize,
+    pub errors: usize,
+    pub total: Score,
+    pub complete_matches: usize,
+    pub no_crossing: usize,
+    pub two_or_less_crossing: usize,
+}
+
+impl Summary {
+    pub fn add(&mut self, score: &Result<Score, String>) {
+        self.sentences += 1;
+        let Ok(score) = score else {
+            self.errors += 1;
+            return;
+        };
+        let total = &mut self.total;
+        total.length += score.length;
+        total.labeled_matched += score.labeled_matched;
+        total.unlabeled_matched += score.unlabeled_matched;
+        total.gold_brackets += score.gold_brackets;
+        total.test_brackets += score.test_brackets;
+        total.crossing += score.crossing;
+        total.words += score.words;
+        total.correct_tags += score.correct_tags;
+        if score.labeled_matched == score.gold_brackets
+            && score.labeled_matched == score.test_brackets
+        {
+            self.complete_matches += 1;
+        }
+        if score.crossing == 0 {
+            self.no_crossing += 1;
+        }
+        if score.crossing <= 2 {
+            self.two_or_less_crossing += 1;
+        }
+    }
+
+    pub fn valid(&self) -> usize {
+        self.sentences - self.errors
+    }
+
+    pub fn recall(&self, labeled: bool) -> f64 {
+        percent(self.total.matched(labeled), self.total.gold_brackets)
+    }
+
+    pub fn precision(&self, labeled: bool) -> f64 {
+        percent(self.total.matched(labeled), self.total.test_brackets)
+    }
+
+    pub fn f_measure(&self, labeled: bool) -> f64 {
+        f_measure(self.recall(labeled), self.precision(labeled))
+    }
+
+    pub fn average_crossing(&self) -> f64 {
+        if self.valid() == 0 {
+            0.0
+        } else {
+            self.total.crossing as f64 / self.valid() as f64
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        let valid = self.valid();
+        Json::object([
+            ("sentences", self.sentences.into()),
+            ("errors", self.errors.into()),
+            ("valid", valid.into()),
+            ("recall", self.recall(true).into()),
+            ("precision", self.precision(true).into()),
+            ("f_measure", self.f_measure(true).into()),
+            ("unlabeled_recall", self.recall(false).into()),
+            ("unlabeled_precision", self.precision(false).into()),
+            ("unlabeled_f_measure", self.f_measure(false).into()),
+            ("complete_match", percent(self.complete_matches, valid).into()),
+            ("average_crossing", self.average_crossing().into()),
+            ("no_crossing", percent(self.no_crossing, valid).into()),
+            ("two_or_less_crossing", percent(self.two_or_less_crossing, valid).into()),
+            (
+                "tagging_accuracy",
+                percent(self.total.correct_tags, self.total.words).into(),
+            ),
+        ])
+    }
+
+    fn print(&self) {
+        let valid = self.valid();
+        println!("Number of sentence        = {:6}", self.sentences);
+        println!("Number of Error sentence  = {:6}", self.errors);
+        println!("Number of Valid sentence  = {:6}", valid);
+        println!("Bracketing Recall         = {:6.2}", self.recall(true));
+        println!("Bracketing Precision      = {:6.2}", self.precision(true));
+        println!("Bracketing FMeasure       = {:6.2}", self.f_measure(true));
+        println!("Unlabeled Recall          = {:6.2}", self.recall(false));
+        println!("Unlabeled Precision       = {:6.2}", self.precision(false));
+        println!("Unlabeled FMeasure        = {:6.2}", self.f_measure(false));
+        println!(
+            "Complete match            = {:6.2}",
+            percent(self.complete_matches, valid)
+        );
+        println!("Average crossing          = {:6.2}", self.average_crossing());
+        println!(
+            "No crossing               = {:6.2}",
+            percent(self.no_crossing, valid)
+        );
+        println!(
+            "2 or less crossing        = {:6.2}",
+            percent(self.two_or_less_crossing, valid)
+        );
+        println!(
+            "Tagging accuracy          = {:6.2}",
+            percent(self.total.correct_tags, self.total.words)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GOLD: &str = "(ROOT (S (NP-SBJ (DT the) (NN dog)) (VP (VBD saw) (NP (DT a) (NN cat))) (. .)))";
+
+    pub fn tree_text(text: &str) -> TreeText {
+        TreeText {
+            text: text.to_string(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    #[test]
+    fn treebank_root_test() {
+        // the gold tree in the form of the Penn Treebank files
+        let parameters = Parameters::default();
+        let gold = "( (S (NP-SBJ (DT the) (NN dog)) (VP (VBD saw) (NP (DT a) (NN cat))) (. .)) )";
+        let gold = read_sentence(&tree_text(gold), &parameters).unwrap();
+        let test = read_sentence(&tree_text(GOLD), &parameters).unwrap();
+        let score = score(&gold, &test, &parameters).unwrap();
+        assert_eq!(score.gold_brackets, 5);
+        assert_eq!(score.labeled_matched, 5);
+    }
+
+    #[test]
+    fn sentence_test() {
+        let mut parameters = Parameters::default();
+        let gold = read_sentence(&tree_text(GOLD), &parameters).unwrap();
+        assert_eq!(gold.words, vec!["the", "dog", "saw", "a", "cat", "."]);
+        assert_eq!(gold.brackets.len(), 5);
+        assert!(gold.brackets.contains(&Bracket {
+            label: "NP".to_string(),
+            start: 0,
+            end: 2
+        }));
+        parameters.delete_labels.insert(".".to_string());
+        parameters.delete_labels.insert("ROOT".to_string());
+        let gold = read_sentence(&tree_text(GOLD), &parameters).unwrap();
+        assert_eq!(gold.words.len(), 5);
+        assert_eq!(gold.brackets.len(), 4);
+        assert!(gold.brackets.contains(&Bracket {
+            label: "S".to_string(),
+            start: 0,
+            end: 5
+        }));
+    }
+
+    #[test]
+    fn score_test() {
+        let parameters = Parameters::default();
+        let gold = read_sentence(&tree_text(GOLD), &parameters).unwrap();
+        let test = read_sentence(
+            &tree_text(
+                "(ROOT (S (NP (DT the) (NN dog)) (VP (VBD saw) (DT a)) (NP (NN cat) (. .))))",
+            ),
+            &parameters,
+        )
+        .unwrap();
+        let score = score(&gold, &test, &parameters).unwrap();
+        assert_eq!(score.gold_brackets, 5);
+        assert_eq!(score.test_brackets, 5);
+        assert_eq!(score.labeled_matched, 3);
+        assert_eq!(score.unlabeled_matched, 3);
+        // VP 2..4 and NP 4..6 cross the gold VP 2..5
+        assert_eq!(score.crossing, 2);
+        assert_eq!(score.correct_tags, 6);
+    }
+
+    #[test]
+    fn noparse_test() {
+        let parameters = Parameters::default();
+        let gold = read_sentence(&tree_text(GOLD), &parameters).unwrap();
+        let test = read_sentence(&tree_text("(NOPARSE the dog saw a cat .)"), &parameters).unwrap();
+        let score = score(&gold, &test, &parameters).unwrap();
+        assert_eq!(score.test_brackets, 1);
+        assert_eq!(score.labeled_matched, 0);
+        // ROOT and S share the span of the sentence but it is matched only once
+        assert_eq!(score.unlabeled_matched, 1);
+        assert_eq!(score.correct_tags, 0);
+        let test = read_sentence(&tree_text("(NOPARSE the dog)"), &parameters).unwrap();
+        assert!(super::score(&gold, &test, &parameters).is_err());
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+
+use super::{Bracket, Score, Sentence, Summary, f_measure, percent};
+use crate::json::Json;
+
+/// sentences are grouped into buckets of this many words
+pub const LENGTH_BUCKET: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BreakdownFormat {
+    /// tables after the evalb summary
+    Text,
+    /// one JSON document with the summary and the breakdowns
+    Json,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LabelCounts {
+    pub gold: usize,
+    pub test: usize,
+    pub matched: usize,
+}
+
+/// error categories after Kummerfeld et al. (2012), assigned to each bracket that is missing
+/// from or extra in the prediction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorKind {
+    PpAttachment,
+    ClauseAttachment,
+    ModifierAttachment,
+    NpAttachment,
+    VpAttachment,
+    Coordination,
+    Unary,
+    SingleWordPhrase,
+    DifferentLabel,
+    NpInternal,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::PpAttachment => "PP attachment",
+            ErrorKind::ClauseAttachment => "Clause attachment",
+            ErrorKind::ModifierAttachment => "Modifier attachment",
+            ErrorKind::NpAttachment => "NP attachment",
+            ErrorKind::VpAttachment => "VP attachment",
+            ErrorKind::Coordination => "Co-ordination",
+            ErrorKind::Unary => "Unary",
+            ErrorKind::SingleWordPhrase => "Single word phrase",
+            ErrorKind::DifferentLabel => "Different label",
+            ErrorKind::NpInternal => "NP internal structure",
+            ErrorKind::Other => "Other",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ErrorCounts {
+    /// gold brackets that the prediction lacks
+    pub missing: usize,
+    /// predicted brackets that are not in the gold tree
+    pub extra: usize,
+}
+
+/// where the predicted trees differ from the gold trees
+#[derive(Debug, Default)]
+pub struct Breakdown {
+    pub labels: BTreeMap<String, LabelCounts>,
+    /// (gold label, predicted label) of spans that are in both trees with different labels
+    pub confusion: BTreeMap<(String, String), usize>,
+    /// summaries of the sentences by their length bucket
+    pub lengths: BTreeMap<usize, Summary>,
+    pub errors: BTreeMap<ErrorKind, ErrorCounts>,
+}
+
+/// returns the brackets of `brackets` that are left after each is matched at most once with
+/// a bracket of `other`
+fn unmatched<'a>(brackets: &'a [Bracket], other: &[Bracket]) -> Vec<&'a Bracket> {
+    let mut used = vec![false; other.len()];
+    brackets
+        .iter()
+        .filter(|bracket| {
+            match other
+                .iter()
+                .enumerate()
+                .position(|(i, e)| !used[i] && e == *bracket)
+            {
+                Some(i) => {
+                    used[i] = true;
+                    false
+                }
+                None => true,
+            }
+        })
+        .collect()
+}
+
+fn same_span(first: &Bracket, second: &Bracket) -> bool {
+    first.start == second.start && first.end == second.end
+}
+
+fn is_coordination(gold: &Sentence, position: usize) -> bool {
+    matches!(gold.tags.get(position), Some(Some(tag)) if tag == "CC" || tag == "CONJP")
+}
+
+fn attachment_kind(label: &str) -> Option<ErrorKind> {
+    match label {
+        "PP" => Some(ErrorKind::PpAttachment),
+        "NP" => Some(ErrorKind::NpAttachment),
+        "VP" => Some(ErrorKind::VpAttachment),
+        _ if label.starts_with('S') => Some(ErrorKind::ClauseAttachment),
+        _ if ["ADVP", "ADJP", "RB", "JJ", "QP"]
+            .iter()
+            .any(|e| label.starts_with(e)) =>
+        {
+            Some(ErrorKind::ModifierAttachment)
+        }
+        _ => None,
+    }
+}
+
+/// returns the span of the phrase adjoined by `bracket`: a bracket `X -> X Y` or `X -> Y X`
+/// without a counterpart only exists to attach `Y` to the inner `X`
+fn adjunction(bracket: &Bracket, own: &[Bracket]) -> Option<(usize, usize)> {
+    let inside = |e: &&Bracket| bracket.start <= e.start && e.end <= bracket.end && !same_span(e, bracket);
+    let left = own
+        .iter()
+        .filter(inside)
+        .filter(|e| e.start == bracket.start)
+        .max_by_key(|e| e.end)?;
+    let right = own
+        .iter()
+        .filter(inside)
+        .filter(|e| e.end == bracket.end)
+        .min_by_key(|e| e.start)?;
+    if left.end != right.start {
+        None
+    } else if left.label == bracket.label {
+        Some((right.start, right.end))
+    } else if right.label == bracket.label {
+        Some((left.start, left.end))
+    } else {
+        None
+    }
+}
+
+/// assigns an error category to `bracket` of the tree with the brackets `own`, `other` are the
+/// unmatched brackets of the other tree
+fn classify(bracket: &Bracket, own: &[Bracket], other: &[&Bracket], gold: &Sentence) -> ErrorKind {
+    if other.iter().any(|e| same_span(e, bracket)) {
+        return ErrorKind::DifferentLabel;
+    }
+    if own.iter().filter(|e| same_span(e, bracket)).count() > 1 {
+        return ErrorKind::Unary;
+    }
+    if bracket.end - bracket.start == 1 {
+        return ErrorKind::SingleWordPhrase;
+    }
+    // the same phrase with one boundary moved: the words in between were attached elsewhere
+    let moved = other
+        .iter()
+        .find_map(|e| {
+            if e.label != bracket.label || (e.start == bracket.start) == (e.end == bracket.end) {
+                None
+            } else if e.start == bracket.start {
+                Some((e.end.min(bracket.end), e.end.max(bracket.end)))
+            } else {
+                Some((e.start.min(bracket.start), e.start.max(bracket.start)))
+            }
+        })
+        .or_else(|| adjunction(bracket, own));
+    if let Some((start, end)) = moved {
+        if (start.saturating_sub(1)..end).any(|i| is_coordination(gold, i)) {
+            return ErrorKind::Coordination;
+        }
+        // brackets are in post-order so the last one with the span is the outermost
+        let label = gold
+            .brackets
+            .iter()
+            .rev()
+            .chain(own.iter().rev())
+            .find(|e| e.start == start && e.end == end)
+            .map(|e| e.label.as_str())
+            .or_else(|| match &gold.tags[start..end] {
+                [Some(tag)] => Some(tag.as_str()),
+                _ => None,
+            });
+        if let Some(kind) = label.and_then(attachment_kind) {
+            return kind;
+        }
+    }
+    if (bracket.start..bracket.end).any(|i| is_coordination(gold, i)) {
+        return ErrorKind::Coordination;
+    }
+    if ["NP", "NML", "NX", "QP"].contains(&bracket.label.as_str())
+        && gold.brackets.iter().any(|e| {
+            e.label == "NP"
+                && e.start <= bracket.start
+                && bracket.end <= e.end
+                && !same_span(e, bracket)
+        })
+    {
+        return ErrorKind::NpInternal;
+    }
+    ErrorKind::Other
+}
+
+impl Breakdown {
+    pub fn add(&mut self, gold: &Sentence, test: &Sentence, score: &Score) {
+        let missing = unmatched(&gold.brackets, &test.brackets);
+        let extra = unmatched(&test.brackets, &gold.brackets);
+
+        for bracket in &gold.brackets {
+            self.labels.entry(bracket.label.clone()).or_default().gold += 1;
+        }
+        for bracket in &test.brackets {
+            self.labels.entry(bracket.label.clone()).or_default().test += 1;
+        }
+        for (label, counts) in self.labels.iter_mut() {
+            counts.matched += gold.brackets.iter().filter(|e| &e.label == label).count()
+                - missing.iter().filter(|e| &e.label == label).count();
+        }
+
+        let mut confused = vec![false; extra.len()];
+        for bracket in &missing {
+            if let Some(i) = (0..extra.len()).find(|i| !confused[*i] && same_span(extra[*i], bracket))
+            {
+                confused[i] = true;
+                *self
+                    .confusion
+                    .entry((bracket.label.clone(), extra[i].label.clone()))
+                    .or_default() += 1;
+            }
+        }
+
+        self.lengths
+            .entry(score.length.saturating_sub(1) / LENGTH_BUCKET)
+            .or_default()
+            .add(&Ok(score.clone()));
+
+        for bracket in &missing {
+            let kind = classify(bracket, &gold.brackets, &extra, gold);
+            self.errors.entry(kind).or_default().missing += 1;
+        }
+        for bracket in &extra {
+            let kind = classify(bracket, &test.brackets, &missing, gold);
+            self.errors.entry(kind).or_default().extra += 1;
+        }
+    }
+
+    fn bucket_name(bucket: usize) -> String {
+        format!("{}-{}", bucket * LENGTH_BUCKET + 1, (bucket + 1) * LENGTH_BUCKET)
+    }
+
+    pub fn print(&self) {
+        println!();
+        println!("=== Labels ===");
+        println!();
+        println!("Label            Gold    Test Matched  Recall   Prec. FMeasure");
+        for (label, counts) in &self.labels {
+            let recall = percent(counts.matched, counts.gold);
+            let precision = percent(counts.matched, counts.test);
+            println!(
+                "{:<14} {:>6} {:>7} {:>7} {:>7.2} {:>7.2} {:>8.2}",
+                label,
+                counts.gold,
+                counts.test,
+                counts.matched,
+                recall,
+                precision,
+                f_measure(recall, precision)
+            );
+        }
+
+        println!();
+        println!("=== Label confusion (gold -> predicted) ===");
+        println!();
+        let mut confusion: Vec<_> = self.confusion.iter().collect();
+        confusion.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for ((gold, test), count) in confusion {
+            println!("{:<14} -> {:<14} {:>6}", gold, test, count);
+        }
+
+        println!();
+        println!("=== Sentence length ===");
+        println!();
+        println!("Length    Sent.  Recall   Prec. FMeasure Complete");
+        for (bucket, summary) in &self.lengths {
+            println!(
+                "{:<8} {:>6} {:>7.2} {:>7.2} {:>8.2} {:>8.2}",
+                Self::bucket_name(*bucket),
+                summary.sentences,
+                summary.recall(true),
+                summary.precision(true),
+                summary.f_measure(true),
+                percent(summary.complete_matches, summary.valid())
+            );
+        }
+
+        println!();
+        println!("=== Errors ===");
+        println!();
+        println!("Error type              Missing   Extra   Total");
+        let mut errors: Vec<_> = self.errors.iter().collect();
+        errors.sort_by_key(|(kind, counts)| (std::cmp::Reverse(counts.missing + counts.extra), **kind));
+        for (kind, counts) in errors {
+            println!(
+                "{:<22} {:>8} {:>7} {:>7}",
+                kind.name(),
+                counts.missing,
+                counts.extra,
+                counts.missing + counts.extra
+            );
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        let labels = self.labels.iter().map(|(label, counts)| {
+            let recall = percent(counts.matched, counts.gold);
+            let precision = percent(counts.matched, counts.test);
+            (
+                label.clone(),
+                Json::object([
+                    ("gold", counts.gold.into()),
+                    ("predicted", counts.test.into()),
+                    ("matched", counts.matched.into()),
+                    ("recall", recall.into()),
+                    ("precision", precision.into()),
+                    ("f_measure", f_measure(recall, precision).into()),
+                ]),
+            )
+        });
+        let confusion = self.confusion.iter().map(|((gold, test), count)| {
+            Json::object([
+                ("gold", gold.as_str().into()),
+                ("predicted", test.as_str().into()),
+                ("count", (*count).into()),
+            ])
+        });
+        let lengths = self.lengths.iter().map(|(bucket, summary)| {
+            Json::object([
+                ("min", (bucket * LENGTH_BUCKET + 1).into()),
+                ("max", ((bucket + 1) * LENGTH_BUCKET).into()),
+                ("summary", summary.to_json()),
+            ])
+        });
+        let errors = self.errors.iter().map(|(kind, counts)| {
+            (
+                kind.name(),
+                Json::object([
+                    ("missing", counts.missing.into()),
+                    ("extra", counts.extra.into()),
+                ]),
+            )
+        });
+        Json::object([
+            ("labels", Json::object(labels)),
+            ("confusion", Json::Array(confusion.collect())),
+            ("lengths", Json::Array(lengths.collect())),
+            ("errors", Json::object(errors)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eval::{parameters::Parameters, read_sentence, score, test::tree_text};
+
+    fn breakdown(gold: &str, test: &str) -> Breakdown {
+        let parameters = Parameters::default();
+        let gold = read_sentence(&tree_text(gold), &parameters).unwrap();
+        let test = read_sentence(&tree_text(test), &parameters).unwrap();
+        let score = score(&gold, &test, &parameters).unwrap();
+        let mut breakdown = Breakdown::default();
+        breakdown.add(&gold, &test, &score);
+        breakdown
+    }
+
+    #[test]
+    fn pp_attachment_test() {
+        let breakdown = breakdown(
+            "(S (NP (PRP I)) (VP (VBD saw) (NP (NP (DT the) (NN man)) (PP (IN with) (NP (DT a) (NN telescope))))))",
+            "(S (NP (PRP I)) (VP (VBD saw) (NP (DT the) (NN man)) (PP (IN with) (NP (DT a) (NN telescope)))))",
+        );
+        assert_eq!(
+            breakdown.errors[&ErrorKind::PpAttachment],
+            ErrorCounts {
+                missing: 1,
+                extra: 0
+            }
+        );
+        assert_eq!(breakdown.labels["NP"].gold, 4);
+        assert_eq!(breakdown.labels["NP"].matched, 3);
+        assert_eq!(breakdown.lengths[&0].sentences, 1);
+    }
+
+    #[test]
+    fn label_test() {
+        let breakdown = breakdown(
+            "(S (NP (PRP I)) (VP (VBD left) (ADVP (RB very) (RB early))))",
+            "(S (NP (PRP I)) (VP (VBD left) (ADJP (RB very) (RB early))))",
+        );
+        assert_eq!(breakdown.confusion[&("ADVP".to_string(), "ADJP".to_string())], 1);
+        assert_eq!(
+            breakdown.errors[&ErrorKind::DifferentLabel],
+            ErrorCounts {
+                missing: 1,
+                extra: 1
+            }
+        );
+        let json = breakdown.to_json().to_string();
+        assert!(json.contains(r#"{"gold":"ADVP","predicted":"ADJP","count":1}"#));
+    }
+}
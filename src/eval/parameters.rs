@@ -0,0 +1,117 @@
+use std::{fs, path::Path, process::exit};
+
+use foldhash::{HashMap, HashSet};
+
+use crate::transform::strip_function_tags;
+
+/// the settings of an evalb parameter file
+#[derive(Debug, PartialEq, Eq)]
+pub struct Parameters {
+    /// compare the labels of brackets and not only their spans
+    pub labeled: bool,
+    /// sentences up to this length are summarised separately
+    pub cutoff_length: usize,
+    /// brackets with these labels are ignored, words with these tags are deleted
+    pub delete_labels: HashSet<String>,
+    /// words with these tags do not count for the sentence length
+    pub delete_labels_for_length: HashSet<String>,
+    /// labels that are treated as the same label
+    pub equal_labels: HashMap<String, String>,
+    /// words that are treated as the same word
+    pub equal_words: HashMap<String, String>,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Self {
+            labeled: true,
+            cutoff_length: 40,
+            delete_labels: HashSet::default(),
+            delete_labels_for_length: HashSet::default(),
+            equal_labels: HashMap::default(),
+            equal_words: HashMap::default(),
+        }
+    }
+}
+
+impl Parameters {
+    pub fn from_file(path: &Path) -> Self {
+        let Ok(content) = fs::read_to_string(path) else {
+            eprintln!("cannot read parameter file {}", path.display());
+            exit(1);
+        };
+        Self::parse(&content).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path.display(), e);
+            exit(1);
+        })
+    }
+
+    /// reads the keys of evalb parameter files, unknown keys like `DEBUG` are ignored
+    fn parse(content: &str) -> Result<Self, String> {
+        let mut parameters = Self::default();
+        for (line_number, line) in content.lines().enumerate() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let number = |value: &str| {
+                value
+                    .parse::<usize>()
+                    .map_err(|e| format!("line {}: {}", line_number + 1, e))
+            };
+            match tokens[..] {
+                ["LABELED", value] => parameters.labeled = number(value)? != 0,
+                ["CUTOFF_LEN", value] => parameters.cutoff_length = number(value)?,
+                ["DELETE_LABEL", label] => {
+                    parameters.delete_labels.insert(label.to_string());
+                }
+                ["DELETE_LABEL_FOR_LENGTH", label] => {
+                    parameters.delete_labels_for_length.insert(label.to_string());
+                }
+                ["EQ_LABEL", first, second] => {
+                    parameters
+                        .equal_labels
+                        .insert(second.to_string(), first.to_string());
+                }
+                ["EQ_WORD", first, second] => {
+                    parameters
+                        .equal_words
+                        .insert(second.to_string(), first.to_string());
+                }
+                _ => {}
+            }
+        }
+        Ok(parameters)
+    }
+
+    /// returns the label that is compared: function tags and indices are removed as evalb does
+    /// and equal labels are replaced by the first label of their `EQ_LABEL` line
+    pub fn label<'a>(&'a self, label: &'a str) -> &'a str {
+        let label = strip_function_tags(label);
+        self.equal_labels.get(label).map_or(label, |e| &e[..])
+    }
+
+    pub fn word<'a>(&'a self, word: &'a str) -> &'a str {
+        self.equal_words.get(word).map_or(word, |e| &e[..])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let parameters = Parameters::parse(
+            "DEBUG 0\nLABELED 1\nCUTOFF_LEN 10\nDELETE_LABEL TOP\nDELETE_LABEL ,\n\
+             DELETE_LABEL_FOR_LENGTH -NONE-\nEQ_LABEL ADVP PRT\nEQ_WORD Example example\n",
+        )
+        .unwrap();
+        assert!(parameters.labeled);
+        assert_eq!(parameters.cutoff_length, 10);
+        assert!(parameters.delete_labels.contains(","));
+        assert!(parameters.delete_labels_for_length.contains("-NONE-"));
+        assert_eq!(parameters.label("PRT"), "ADVP");
+        assert_eq!(parameters.label("NP-SBJ-1"), "NP");
+        assert_eq!(parameters.label("-NONE-"), "-NONE-");
+        assert_eq!(parameters.word("example"), "Example");
+        assert!(Parameters::parse("CUTOFF_LEN x").is_err());
+    }
+}
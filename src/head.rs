@@ -0,0 +1,387 @@
+use std::{fs, path::Path, process::exit};
+
+use clap::ValueEnum;
+use foldhash::{HashMap, HashMapExt};
+
+use crate::{induce::parse_tree::ParseTree, markers::Markers};
+
+/// head percolation table from Collins' thesis in the form `LABEL DIRECTION CATEGORIES…`
+const COLLINS_HEAD_RULES: &str = "\
+ADJP left NNS QP NN $ ADVP JJ VBN VBG ADJP JJR NP JJS DT FW RBR RBS SBAR RB
+ADVP right RB RBR RBS FW ADVP TO CD JJR JJ IN NP JJS NN
+CONJP right CC RB IN
+FRAG right
+INTJ left
+LST right LS :
+NAC left NN NNS NNP NNPS NP NAC EX $ CD QP PRP VBG JJ JJS JJR ADJP FW
+NP rightdis NN NNP NNPS NNS NX POS JJR
+NP left NP
+NP rightdis $ ADJP PRN
+NP right CD
+NP rightdis JJ JJS RB QP
+NX left NN NNS NNP NNPS NX JJR
+PP right IN TO VBG VBN RP FW
+PRN left
+PRT right RP
+QP left $ IN NNS NN JJ RB DT CD NCD QP JJR JJS
+RRC right VP NP ADVP ADJP PP
+S left TO IN VP S SBAR ADJP UCP NP
+SBAR left WHNP WHPP WHADVP WHADJP IN DT S SQ SINV SBAR FRAG
+SBARQ left SQ S SINV SBARQ FRAG
+SINV left VBZ VBD VBP VB MD VP S SINV ADJP NP
+SQ left VBZ VBD VBP VB MD VP SQ
+UCP right
+VP left TO VBD VBN MD VBZ VB VBG VBP VP ADJP NN NNS NP
+WHADJP left CC WRB JJ ADJP
+WHADVP right CC WRB
+WHNP left WDT WP WP$ WHADJP WHPP WHNP
+WHPP right IN TO FW
+X right
+";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Direction {
+    /// for each category search the children from left to right
+    Left,
+    /// for each category search the children from right to left
+    Right,
+    /// search the children from left to right for any of the categories
+    LeftDis,
+    /// search the children from right to left for any of the categories
+    RightDis,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct HeadRule {
+    direction: Direction,
+    categories: Vec<String>,
+}
+
+/// a head percolation table which selects the head child of each node by its label
+#[derive(Debug, PartialEq, Eq)]
+pub struct HeadRules {
+    rules: HashMap<String, Vec<HeadRule>>,
+}
+
+impl HeadRules {
+    /// the head rules of Collins (1999)
+    pub fn collins() -> Self {
+        Self::parse(COLLINS_HEAD_RULES).expect("the built in head rules are correct")
+    }
+
+    /// reads a head percolation table with one rule `LABEL DIRECTION CATEGORIES…` per line
+    ///
+    /// the directions are `left`, `right`, `leftdis` and `rightdis`, rules of the same label are
+    /// tried in the order of the file and lines starting with `#` are ignored
+    pub fn from_file(path: &Path) -> Self {
+        let Ok(table) = fs::read_to_string(path) else {
+            eprintln!("cannot read head rules {}", path.display());
+            exit(1);
+        };
+        Self::parse(&table).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path.display(), e);
+            exit(1);
+        })
+    }
+
+    fn parse(table: &str) -> Result<Self, String> {
+        let mut rules: HashMap<String, Vec<HeadRule>> = HashMap::new();
+        for (line_number, line) in table.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            let Some(label) = tokens.next().filter(|e| !e.starts_with('#')) else {
+                continue;
+            };
+            let direction = match tokens.next() {
+                Some("left") => Direction::Left,
+                Some("right") => Direction::Right,
+                Some("leftdis") => Direction::LeftDis,
+                Some("rightdis") => Direction::RightDis,
+                _ => return Err(format!("line {} has no valid direction", line_number + 1)),
+            };
+            rules.entry(label.to_string()).or_default().push(HeadRule {
+                direction,
+                categories: tokens.map(str::to_string).collect(),
+            });
+        }
+        Ok(Self { rules })
+    }
+
+    /// returns the index of the head in the `children` labels of a node with the `label`
+    ///
+    /// labels are compared without the annotations written with `markers` and function tags
+    pub fn head_child(&self, label: &str, children: &[&str], markers: &Markers) -> usize {
+        let children: Vec<&str> = children.iter().map(|e| base_label(e, markers)).collect();
+        let Some(rules) = self.rules.get(base_label(label, markers)) else {
+            return 0;
+        };
+        for rule in rules {
+            let positions: Box<dyn Iterator<Item = usize>> = match rule.direction {
+                Direction::Left | Direction::LeftDis => Box::new(0..children.len()),
+                Direction::Right | Direction::RightDis => Box::new((0..children.len()).rev()),
+            };
+            let positions: Vec<usize> = positions.collect();
+            let found = match rule.direction {
+                Direction::Left | Direction::Right => rule.categories.iter().find_map(|category| {
+                    positions.iter().copied().find(|e| children[*e] == category)
+                }),
+                Direction::LeftDis | Direction::RightDis => positions
+                    .iter()
+                    .copied()
+                    .find(|e| rule.categories.iter().any(|c| c == children[*e])),
+            };
+            if let Some(found) = found {
+                return found;
+            }
+        }
+        match rules[0].direction {
+            Direction::Left | Direction::LeftDis => 0,
+            Direction::Right | Direction::RightDis => children.len() - 1,
+        }
+    }
+
+    /// returns the index of the child of `tree` that contains the head
+    ///
+    /// children that `binarise` introduced for the same node (`X|<…>`) are searched through, so
+    /// the head is the same as in the unbinarised tree
+    pub fn find_head<T: AsRef<str>>(&self, tree: &ParseTree<T>, markers: &Markers) -> usize {
+        let base = base_label(tree.root.as_ref(), markers);
+        let mut labels = Vec::new();
+        let mut origins = Vec::new();
+        for (index, child) in tree.children.iter().enumerate() {
+            flatten_intermediate(child, base, index, markers, &mut labels, &mut origins);
+        }
+        origins[self.head_child(base, &labels, markers)]
+    }
+}
+
+fn flatten_intermediate<'a, T: AsRef<str>>(
+    tree: &'a ParseTree<T>,
+    base: &str,
+    origin: usize,
+    markers: &Markers,
+    labels: &mut Vec<&'a str>,
+    origins: &mut Vec<usize>,
+) {
+    let label = tree.root.as_ref();
+    let intermediate = markers.intermediate(strip_annotation(label)).is_some();
+    if intermediate && base_label(label, markers) == base && !tree.is_leaf() {
+        for child in &tree.children {
+            flatten_intermediate(child, base, origin, markers, labels, origins);
+        }
+    } else {
+        labels.push(label);
+        origins.push(origin);
+    }
+}
+
+/// returns the label without head annotation, markovisation, parent annotation and function tags
+///
+/// `NP-SBJ|<DT,NN>^<S>[NN]` becomes `NP`, labels starting with `-` like `-NONE-` are kept
+pub fn base_label<'a>(label: &'a str, markers: &Markers) -> &'a str {
+    let label = strip_annotation(label);
+    let label = markers
+        .annotation(label)
+        .map_or(label, |annotation| annotation.base);
+    if label.starts_with('-') {
+        return label;
+    }
+    label.split(['-', '=']).next().unwrap()
+}
+
+/// which head information is added to the labels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LexicalisationMode {
+    /// annotate phrases with the tag of their head: `NP[NN]`
+    Tag,
+    /// annotate all nodes with the tag and word of their head: `NP[NN/dog]`
+    Word,
+}
+
+pub struct Lexicalisation {
+    pub mode: LexicalisationMode,
+    pub head_rules: HeadRules,
+    /// head words that occur at most this often are backed off to their tag
+    pub threshold: u64,
+    /// markers of the trees that `binarise` markovised or parent annotated
+    pub markers: Markers,
+}
+
+/// returns the tree with each node except the root annotated with the head information selected
+/// by `mode`
+pub fn lexicalise<T: AsRef<str>>(
+    tree: &ParseTree<T>,
+    head_rules: &HeadRules,
+    mode: LexicalisationMode,
+    markers: &Markers,
+) -> ParseTree<String> {
+    let (mut lexicalised, _, _) = lexicalise_node(tree, head_rules, mode, markers);
+    lexicalised.root = tree.root.as_ref().to_string();
+    lexicalised
+}
+
+/// returns the lexicalised tree with its head tag and head word
+fn lexicalise_node<'a, T: AsRef<str>>(
+    tree: &'a ParseTree<T>,
+    head_rules: &HeadRules,
+    mode: LexicalisationMode,
+    markers: &Markers,
+) -> (ParseTree<String>, &'a str, &'a str) {
+    let label = tree.root.as_ref();
+    if tree.is_leaf() {
+        return (ParseTree::new(label.to_string()), label, label);
+    }
+    if tree.children.len() == 1 && tree.children[0].is_leaf() {
+        let word = tree.children[0].root.as_ref();
+        let root = match mode {
+            LexicalisationMode::Tag => label.to_string(),
+            LexicalisationMode::Word => annotate(label, label, word, mode, markers),
+        };
+        let preterminal = ParseTree {
+            root,
+            children: vec![ParseTree::new(word.to_string())],
+        };
+        return (preterminal, label, word);
+    }
+    let mut children = Vec::with_capacity(tree.children.len());
+    let mut heads = Vec::with_capacity(tree.children.len());
+    for child in &tree.children {
+        let (child, tag, word) = lexicalise_node(child, head_rules, mode, markers);
+        children.push(child);
+        heads.push((tag, word));
+    }
+    let (tag, word) = heads[head_rules.find_head(tree, markers)];
+    let lexicalised = ParseTree {
+        root: annotate(label, tag, word, mode, markers),
+        children,
+    };
+    (lexicalised, tag, word)
+}
+
+fn annotate(
+    label: &str,
+    tag: &str,
+    word: &str,
+    mode: LexicalisationMode,
+    markers: &Markers,
+) -> String {
+    let tag = base_label(tag, markers);
+    match mode {
+        LexicalisationMode::Tag => format!("{label}[{tag}]"),
+        LexicalisationMode::Word => format!("{label}[{tag}/{word}]"),
+    }
+}
+
+/// splits a lexicalised label `X[T/w]` or `X[T]` into `X`, `T` and the optional `w`
+pub fn split_annotation(label: &str) -> Option<(&str, &str, Option<&str>)> {
+    let start = label.find('[')?;
+    let annotation = label[start + 1..].strip_suffix(']')?;
+    match annotation.split_once('/') {
+        Some((tag, word)) => Some((&label[..start], tag, Some(word))),
+        None => Some((&label[..start], annotation, None)),
+    }
+}
+
+/// returns the label without its head annotation
+pub fn strip_annotation(label: &str) -> &str {
+    split_annotation(label).map_or(label, |(base, _, _)| base)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markers::DEFAULT_MARKERS;
+
+    fn preterminal(tag: &'static str, word: &'static str) -> ParseTree<&'static str> {
+        ParseTree {
+            root: tag,
+            children: vec![ParseTree::new(word)],
+        }
+    }
+
+    #[test]
+    fn head_child_test() {
+        let rules = HeadRules::collins();
+        let markers = Markers::default();
+        assert_eq!(
+            rules.head_child("NP", &["DT", "JJ", "NN", "PP"], &markers),
+            2
+        );
+        assert_eq!(rules.head_child("NP-SBJ", &["NP", "PP"], &markers), 0);
+        assert_eq!(rules.head_child("VP^<S>", &["VBD", "NP"], &markers), 0);
+        assert_eq!(rules.head_child("PP", &["IN", "NP"], &markers), 0);
+        assert_eq!(rules.head_child("FRAG", &["RB", "NP", "."], &markers), 2);
+        assert_eq!(rules.head_child("UNKNOWN", &["A", "B"], &markers), 0);
+    }
+
+    #[test]
+    fn from_table_test() {
+        let rules = HeadRules::parse("# comment\nS right VP\n\nS left NP\n").unwrap();
+        let markers = Markers::default();
+        assert_eq!(rules.head_child("S", &["NP", "VP", "VP"], &markers), 2);
+        assert_eq!(rules.head_child("S", &["NP", "NP"], &markers), 0);
+        assert_eq!(rules.head_child("S", &["PP", "PP"], &markers), 1);
+        assert!(HeadRules::parse("S up VP").is_err());
+    }
+
+    #[test]
+    fn find_head_binarised_test() {
+        // (NP (DT the) (NP|<JJ,NN> (JJ big) (NN dog)))
+        let tree = ParseTree {
+            root: "NP",
+            children: vec![
+                preterminal("DT", "the"),
+                ParseTree {
+                    root: "NP|<JJ,NN>",
+                    children: vec![preterminal("JJ", "big"), preterminal("NN", "dog")],
+                },
+            ],
+        };
+        assert_eq!(HeadRules::collins().find_head(&tree, &DEFAULT_MARKERS), 1);
+
+        // the same tree binarised with other markers
+        let markers = Markers::new("@", "#", ";", "!").unwrap();
+        let tree = ParseTree {
+            root: "NP",
+            children: vec![
+                preterminal("DT", "the"),
+                ParseTree {
+                    root: "NP@JJ;NN!",
+                    children: vec![preterminal("JJ", "big"), preterminal("NN", "dog")],
+                },
+            ],
+        };
+        assert_eq!(HeadRules::collins().find_head(&tree, &markers), 1);
+    }
+
+    #[test]
+    fn lexicalise_test() {
+        let tree = ParseTree {
+            root: "ROOT",
+            children: vec![ParseTree {
+                root: "NP",
+                children: vec![preterminal("DT", "the"), preterminal("NN", "dog")],
+            }],
+        };
+        let rules = HeadRules::collins();
+        assert_eq!(
+            lexicalise(&tree, &rules, LexicalisationMode::Tag, &DEFAULT_MARKERS).to_string(),
+            "(ROOT (NP[NN] (DT the) (NN dog)))"
+        );
+        assert_eq!(
+            lexicalise(&tree, &rules, LexicalisationMode::Word, &DEFAULT_MARKERS).to_string(),
+            "(ROOT (NP[NN/dog] (DT[DT/the] the) (NN[NN/dog] dog)))"
+        );
+    }
+
+    #[test]
+    fn split_annotation_test() {
+        assert_eq!(split_annotation("NP"), None);
+        assert_eq!(split_annotation("NP[NN]"), Some(("NP", "NN", None)));
+        assert_eq!(
+            split_annotation("NP|<DT>[NN/a/b]"),
+            Some(("NP|<DT>", "NN", Some("a/b")))
+        );
+        assert_eq!(base_label("NP-SBJ|<DT,NN>^<S>[NN]", &DEFAULT_MARKERS), "NP");
+        assert_eq!(base_label("-NONE-", &DEFAULT_MARKERS), "-NONE-");
+    }
+}
@@ -1,13 +1,25 @@
 use std::{
-    collections::HashSet, fs::File, io::{self, Write}, process::exit
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::exit,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
 };
 
+use crate::head::{Lexicalisation, lexicalise, split_annotation};
+use estimate::{Smoothing, smooth_grammar};
 use foldhash::{HashMap, HashMapExt};
-use parse_tree::ParseTree;
+use nom::{Parser, multi::many0};
+use parse_tree::{ParseTree, TreeReader};
+use suffix_model::SuffixModel;
 
+pub mod estimate;
 pub mod parse_tree;
+pub mod suffix_model;
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Rhs {
     Terminal(String),
     NonTerminals(Vec<String>),
@@ -15,8 +27,65 @@ pub enum Rhs {
 
 
 
-pub fn induce(grammar: &Option<String>) {
-    let (mut rules, mut lexicon, mut words) = match grammar {
+#[allow(clippy::too_many_arguments)]
+pub fn induce(
+    grammar: &Option<String>,
+    counts: bool,
+    suffix_threshold: Option<u64>,
+    add: &Option<PathBuf>,
+    inputs: &[PathBuf],
+    smoothing: &Smoothing,
+    lexicalisation: Option<&Lexicalisation>,
+) {
+    let (mut rules, mut lexicon, mut words) = grammar_locations(grammar);
+    let mut absolute_grammar = match add {
+        Some(path) => read_counts(path),
+        None => HashMap::new(),
+    };
+    let counted = if inputs.is_empty() {
+        count_grammar(
+            &mut absolute_grammar,
+            io::stdin().lock(),
+            "stdin",
+            lexicalisation,
+        )
+    } else {
+        count_files(&collect_files(inputs), lexicalisation)
+            .map(|counts| merge_counts(&mut absolute_grammar, counts))
+    };
+    if let Err(message) = counted {
+        eprintln!("{}", message);
+        exit(1);
+    }
+    // the counts would be mixed into the grammar on stdout, so GRAMMAR is required for them
+    if let Some(grammar_location) = grammar.as_ref().filter(|_| counts) {
+        let mut counts_location = File::create(format!("{grammar_location}.counts"))
+            .expect("GRAMMAR.counts is not a correct location");
+        write_counts(&mut counts_location, &absolute_grammar);
+    }
+    // like the counts, the suffix model is only written next to GRAMMAR
+    if let Some((grammar_location, threshold)) = grammar.as_ref().zip(suffix_threshold) {
+        let mut suffixes_location = File::create(format!("{grammar_location}.suffixes"))
+            .expect("GRAMMAR.suffixes is not a correct location");
+        SuffixModel::train(&absolute_grammar, threshold).write(&mut suffixes_location);
+    }
+    if let Some(lexicalisation) = lexicalisation {
+        absolute_grammar = back_off_rare_heads(absolute_grammar, lexicalisation.threshold);
+    }
+    let grammar = if smoothing.is_enabled() {
+        smooth_grammar(&absolute_grammar, smoothing)
+    } else {
+        transform_grammar(absolute_grammar)
+    };
+    write_grammar(&mut rules, &mut lexicon, &mut words, &grammar);
+}
+
+/// opens [GRAMMAR].rules, [GRAMMAR].lexicon and [GRAMMAR].words for writing or uses stdout for all
+/// of them if there is no `grammar`
+pub fn grammar_locations(
+    grammar: &Option<String>,
+) -> (Box<dyn Write>, Box<dyn Write>, Box<dyn Write>) {
+    match grammar {
         Some(grammar_location) => {
             let rules_location = File::create(format!("{grammar_location}.rules"))
                 .expect("GRAMMAR.rules is not a correct location");
@@ -35,13 +104,14 @@ pub fn induce(grammar: &Option<String>) {
             Box::new(io::stdout()) as Box<dyn Write>,
             Box::new(io::stdout()) as Box<dyn Write>,
         ),
-    };
-    let grammar = induce_grammar();
-    write_grammar(&mut rules, &mut lexicon, &mut words, &grammar);
+    }
 }
 
 /// writes the grammar rules from the `tree` into `grammar` and counts the ocurrences of each rule
-fn update_grammar(grammar: &mut HashMap<String, HashMap<Rhs, u64>>, tree: ParseTree<&str>) {
+fn update_grammar<T: ToString>(
+    grammar: &mut HashMap<String, HashMap<Rhs, u64>>,
+    tree: ParseTree<T>,
+) {
     tree.execute_for_nodes(&mut |node| {
         if node.is_leaf() {
             return;
@@ -82,29 +152,253 @@ fn transform_grammar(
     grammar
 }
 
-/// gets trees in s-expression form from stdin and returns a pcfg
-pub fn induce_grammar() -> HashMap<String, HashMap<Rhs, f64>> {
+/// reads the trees in s-expression form from `reader` and adds their rule counts to
+/// `absolute_grammar`
+///
+/// `source` names the input in the error message of the first malformed tree
+fn count_grammar(
+    absolute_grammar: &mut HashMap<String, HashMap<Rhs, u64>>,
+    reader: impl BufRead,
+    source: &str,
+    lexicalisation: Option<&Lexicalisation>,
+) -> Result<(), String> {
+    for tree_text in TreeReader::new(reader) {
+        let tree_text = tree_text.map_err(|e| format!("{}, {}", source, e))?;
+        let tree = tree_text
+            .parse()
+            .map_err(|e| format!("{}, {}", source, e))?;
+        match lexicalisation {
+            Some(lexicalisation) => update_grammar(
+                absolute_grammar,
+                lexicalise(
+                    &tree,
+                    &lexicalisation.head_rules,
+                    lexicalisation.mode,
+                    &lexicalisation.markers,
+                ),
+            ),
+            None => update_grammar(absolute_grammar, tree),
+        }
+    }
+    Ok(())
+}
+
+/// counts the rules of all trees in the `files` in parallel and returns the merged counts
+///
+/// the workers stop after the first file that cannot be read, its error is returned
+fn count_files(
+    files: &[PathBuf],
+    lexicalisation: Option<&Lexicalisation>,
+) -> Result<HashMap<String, HashMap<Rhs, u64>>, String> {
+    let next_file = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let workers = thread::available_parallelism()
+        .map(usize::from)
+        .unwrap_or(1)
+        .min(files.len());
+    let grammars: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| scope.spawn(|| count_worker(files, &next_file, &failed, lexicalisation)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("counting thread panicked"))
+            .collect()
+    });
+    let mut absolute_grammar = HashMap::new();
+    for grammar in grammars {
+        merge_counts(&mut absolute_grammar, grammar?);
+    }
+    Ok(absolute_grammar)
+}
+
+/// counts the files that are not taken by another worker until all are counted or one of the
+/// workers `failed`
+fn count_worker(
+    files: &[PathBuf],
+    next_file: &AtomicUsize,
+    failed: &AtomicBool,
+    lexicalisation: Option<&Lexicalisation>,
+) -> Result<HashMap<String, HashMap<Rhs, u64>>, String> {
+    let mut absolute_grammar = HashMap::new();
+    while let Some(file) = files.get(next_file.fetch_add(1, Ordering::Relaxed)) {
+        if failed.load(Ordering::Relaxed) {
+            break;
+        }
+        let counted = match File::open(file) {
+            Ok(reader) => count_grammar(
+                &mut absolute_grammar,
+                BufReader::new(reader),
+                &file.display().to_string(),
+                lexicalisation,
+            ),
+            Err(e) => Err(format!(
+                "cannot open treebank file {}: {}",
+                file.display(),
+                e
+            )),
+        };
+        if counted.is_err() {
+            failed.store(true, Ordering::Relaxed);
+            return counted.map(|_| absolute_grammar);
+        }
+    }
+    Ok(absolute_grammar)
+}
+
+/// replaces the head words that occur at most `threshold` times in the lexicon by their tag
+///
+/// `X[T/w]` becomes `X[T]` for rare words `w`, the counts of rules that become equal are summed up
+fn back_off_rare_heads(
+    absolute_grammar: HashMap<String, HashMap<Rhs, u64>>,
+    threshold: u64,
+) -> HashMap<String, HashMap<Rhs, u64>> {
+    let mut word_count: HashMap<&str, u64> = HashMap::new();
+    for body in absolute_grammar.values() {
+        for (rhs, count) in body {
+            if let Rhs::Terminal(word) = rhs {
+                *word_count.entry(word).or_default() += count;
+            }
+        }
+    }
+    let rare_words: HashSet<String> = word_count
+        .into_iter()
+        .filter(|(_, count)| *count <= threshold)
+        .map(|(word, _)| word.to_string())
+        .collect();
+    let back_off = |label: String| match split_annotation(&label) {
+        Some((base, tag, Some(word))) if rare_words.contains(word) => format!("{base}[{tag}]"),
+        _ => label,
+    };
+    let mut backed_off: HashMap<String, HashMap<Rhs, u64>> = HashMap::new();
+    for (non_terminal, body) in absolute_grammar {
+        let new_body = backed_off.entry(back_off(non_terminal)).or_default();
+        for (rhs, count) in body {
+            let rhs = match rhs {
+                Rhs::Terminal(_) => rhs,
+                Rhs::NonTerminals(non_terminals) => {
+                    Rhs::NonTerminals(non_terminals.into_iter().map(back_off).collect())
+                }
+            };
+            *new_body.entry(rhs).or_default() += count;
+        }
+    }
+    backed_off
+}
+
+/// adds all counts of `other` to `grammar`
+fn merge_counts(
+    grammar: &mut HashMap<String, HashMap<Rhs, u64>>,
+    other: HashMap<String, HashMap<Rhs, u64>>,
+) {
+    for (non_terminal, body) in other {
+        let merged_body = grammar.entry(non_terminal).or_default();
+        for (rhs, count) in body {
+            *merged_body.entry(rhs).or_default() += count;
+        }
+    }
+}
+
+/// returns the `inputs` with every directory replaced by the files it contains, recursively and
+/// in sorted order
+fn collect_files(inputs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            let Ok(entries) = fs::read_dir(input) else {
+                eprintln!("cannot read directory {}", input.display());
+                exit(1);
+            };
+            let mut entries: Vec<PathBuf> = entries
+                .map(|entry| {
+                    entry.map(|entry| entry.path()).unwrap_or_else(|e| {
+                        eprintln!("cannot read directory {}: {}", input.display(), e);
+                        exit(1);
+                    })
+                })
+                .collect();
+            entries.sort();
+            files.extend(collect_files(&entries));
+        } else {
+            files.push(input.clone());
+        }
+    }
+    files
+}
+
+/// reads the rule counts written by `write_counts`
+///
+/// rules have the form `A -> B C 3` and lexical rules the form `A word 3`
+pub fn read_counts(path: &Path) -> HashMap<String, HashMap<Rhs, u64>> {
+    let Ok(file) = File::open(path) else {
+        eprintln!("cannot open counts file {}", path.display());
+        exit(1);
+    };
     let mut absolute_grammar: HashMap<String, HashMap<Rhs, u64>> = HashMap::new();
-    for (line_number, line) in io::stdin().lines().enumerate() {
-        let Ok(line) = line else { continue };
-        let Ok((remaining_line, tree)) = parse_tree::element(&line) else {
-            eprintln!(
-                "Line {} could not be parsed. Probably because it is not a correct parse-tree.",
-                line_number + 1,
-            );
+    for (line_number, line) in BufReader::new(file).lines().enumerate() {
+        let Ok(line) = line else {
+            eprintln!("cannot read line {} of the counts file", line_number + 1);
             exit(1);
         };
-        if remaining_line.trim() != "" {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some((non_terminal, rhs, count)) = parse_count_line(&line) else {
             eprintln!(
-                "Line {} was not completly parsed! Probably because it is not a correct parse-tree. Remainder: {:?}",
+                "Line {} of the counts file is malformed: {:?}",
                 line_number + 1,
-                remaining_line
+                line
             );
             exit(1);
+        };
+        *absolute_grammar
+            .entry(non_terminal)
+            .or_default()
+            .entry(rhs)
+            .or_default() += count;
+    }
+    absolute_grammar
+}
+
+pub fn parse_count_line(line: &str) -> Option<(String, Rhs, u64)> {
+    let (_, mut tokens) = many0(parse_tree::atom).parse(line).ok()?;
+    let count = tokens.pop()?.parse::<u64>().ok()?;
+    match tokens[..] {
+        [non_terminal, "->", ref body @ ..] if !body.is_empty() => Some((
+            non_terminal.to_string(),
+            Rhs::NonTerminals(body.iter().map(|e| e.to_string()).collect()),
+            count,
+        )),
+        [non_terminal, terminal] => Some((
+            non_terminal.to_string(),
+            Rhs::Terminal(terminal.to_string()),
+            count,
+        )),
+        _ => None,
+    }
+}
+
+/// writes the absolute counts of all rules of the grammar to `counts`
+pub fn write_counts(counts: &mut impl Write, grammar: &HashMap<String, HashMap<Rhs, u64>>) {
+    for (non_terminal, value) in grammar {
+        for (body, count) in value {
+            match body {
+                Rhs::Terminal(terminal) => {
+                    writeln!(counts, "{} {} {}", non_terminal, terminal, count)
+                }
+                Rhs::NonTerminals(non_terminals) => {
+                    writeln!(
+                        counts,
+                        "{} -> {} {}",
+                        non_terminal,
+                        non_terminals.join(" "),
+                        count
+                    )
+                }
+            }
+            .expect("cannot write to counts");
         }
-        update_grammar(&mut absolute_grammar, tree);
     }
-    transform_grammar(absolute_grammar)
 }
 
 /// writes the rules of the grammar to `rules` the terminals to `words` and the rules of terminals to
@@ -145,6 +439,10 @@ pub fn write_grammar(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        head::{HeadRules, LexicalisationMode},
+        markers::Markers,
+    };
 
     #[test]
     fn update_grammar_test() {
@@ -219,6 +517,108 @@ mod tests {
         );
     }
 
+    #[test]
+    fn merge_counts_test() {
+        let lines = ["(ROOT (NS hi))", "(ROOT (NS ho))", "(ROOT (NS hi) (NS ho))"];
+        let mut single_stream = HashMap::new();
+        count_grammar(
+            &mut single_stream,
+            lines.join("\n").as_bytes(),
+            "test",
+            None,
+        )
+        .unwrap();
+        let mut merged = HashMap::new();
+        for line in lines {
+            let mut grammar = HashMap::new();
+            count_grammar(&mut grammar, line.as_bytes(), "test", None).unwrap();
+            merge_counts(&mut merged, grammar);
+        }
+        assert_eq!(single_stream, merged);
+
+        let malformed = count_grammar(&mut merged, "(ROOT (NS hi)))".as_bytes(), "test", None);
+        assert_eq!(
+            malformed.unwrap_err(),
+            "test, line 1, column 15: unexpected ')' outside of a tree"
+        );
+        let missing = count_files(&[PathBuf::from("/nonexistent/wsj_0001.mrg")], None);
+        assert!(
+            missing
+                .unwrap_err()
+                .starts_with("cannot open treebank file")
+        );
+    }
+
+    #[test]
+    fn treebank_test() {
+        let mrg = "*x*\n( (S \n    (NP-SBJ (DT the) (NN dog))\n    (VP (VBZ barks))))\n";
+        let mut grammar = HashMap::new();
+        count_grammar(&mut grammar, mrg.as_bytes(), "wsj_0001.mrg", None).unwrap();
+        assert_eq!(
+            grammar["ROOT"],
+            HashMap::from_iter([(Rhs::NonTerminals(vec!["S".to_string()]), 1)])
+        );
+        assert_eq!(
+            grammar["NP-SBJ"],
+            HashMap::from_iter([(
+                Rhs::NonTerminals(vec!["DT".to_string(), "NN".to_string()]),
+                1
+            )])
+        );
+    }
+
+    #[test]
+    fn back_off_rare_heads_test() {
+        let mut grammar = HashMap::new();
+        let lexicalisation = Lexicalisation {
+            mode: LexicalisationMode::Word,
+            head_rules: HeadRules::collins(),
+            threshold: 1,
+            markers: Markers::default(),
+        };
+        count_grammar(
+            &mut grammar,
+            "(ROOT (NP (NN dog)))\n(ROOT (NP (NN dog)))\n(ROOT (NP (NN cat)))".as_bytes(),
+            "test",
+            Some(&lexicalisation),
+        )
+        .unwrap();
+        let grammar = back_off_rare_heads(grammar, 1);
+        assert_eq!(
+            grammar["ROOT"],
+            HashMap::from_iter([
+                (Rhs::NonTerminals(vec!["NP[NN/dog]".to_string()]), 2),
+                (Rhs::NonTerminals(vec!["NP[NN]".to_string()]), 1),
+            ])
+        );
+        assert_eq!(
+            grammar["NN[NN]"],
+            HashMap::from_iter([(Rhs::Terminal("cat".to_string()), 1)])
+        );
+    }
+
+    #[test]
+    fn parse_count_line_test() {
+        assert_eq!(
+            parse_count_line("ROOT -> NS NS 3"),
+            Some((
+                "ROOT".to_string(),
+                Rhs::NonTerminals(vec!["NS".to_string(), "NS".to_string()]),
+                3
+            ))
+        );
+        assert_eq!(
+            parse_count_line("NS hi 2"),
+            Some(("NS".to_string(), Rhs::Terminal("hi".to_string()), 2))
+        );
+        assert_eq!(
+            parse_count_line("NS -> 2"),
+            Some(("NS".to_string(), Rhs::Terminal("->".to_string()), 2))
+        );
+        assert_eq!(parse_count_line("NS hi 0.5"), None);
+        assert_eq!(parse_count_line("NS 2"), None);
+    }
+
     #[test]
     fn transform_grammar_test() {
         let grammar = HashMap::from_iter([
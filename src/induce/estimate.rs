@@ -0,0 +1,392 @@
+use std::borrow::Cow;
+
+use foldhash::{HashMap, HashMapExt, HashSet};
+
+use super::Rhs;
+use crate::{
+    head::strip_annotation,
+    markers::{Annotation, Markers},
+};
+
+/// options for smoothing the rule probabilities of an induced grammar
+#[derive(Debug, Default, Clone)]
+pub struct Smoothing {
+    /// pseudo count added to every observed right hand side of the lengths a non-terminal has
+    pub rules: Option<f64>,
+    /// pseudo count added to every observed word
+    pub lexicon: Option<f64>,
+    /// weight of the less specific context for markovised non-terminals
+    pub backoff: Option<f64>,
+    /// markers of the non-terminals that `binarise` markovised or parent annotated
+    pub markers: Markers,
+}
+
+impl Smoothing {
+    pub fn is_enabled(&self) -> bool {
+        self.rules.is_some() || self.lexicon.is_some() || self.backoff.is_some()
+    }
+}
+
+/// transforms the grammar from absolute counts to a smoothed probability distribution over each
+/// non-terminal
+///
+/// the lexical and the syntactic rules of a non-terminal are smoothed separately and keep the
+/// share of the probability mass they have in the training data
+///
+/// a non-terminal only gets rules with right hand sides of the lengths it was seen with, and
+/// unary rules only if it was seen with them, so that smoothing does not add unary cycles such
+/// as `A -> A`. the mass of the words a tag was not seen with goes to the rule `TAG UNK`, whose
+/// weight is that of one unseen word
+pub fn smooth_grammar(
+    absolute_grammar: &HashMap<String, HashMap<Rhs, u64>>,
+    smoothing: &Smoothing,
+) -> HashMap<String, HashMap<Rhs, f64>> {
+    let mut bodies: HashMap<usize, HashSet<&Rhs>> = HashMap::new();
+    let mut all_words: HashSet<&Rhs> = HashSet::default();
+    for body in absolute_grammar.values() {
+        for rhs in body.keys() {
+            match rhs {
+                Rhs::Terminal(_) => {
+                    all_words.insert(rhs);
+                }
+                Rhs::NonTerminals(children) if children.len() > 1 => {
+                    bodies.entry(children.len()).or_default().insert(rhs);
+                }
+                Rhs::NonTerminals(_) => {}
+            }
+        }
+    }
+    let markers = &smoothing.markers;
+    let pools = smoothing
+        .backoff
+        .map(|_| backoff_pools(absolute_grammar, markers));
+    let unknown = Rhs::Terminal("UNK".to_string());
+
+    let mut grammar = HashMap::new();
+    for (non_terminal, body) in absolute_grammar {
+        let total = body.values().sum::<u64>() as f64;
+        let (lexical, syntactic): (HashMap<&Rhs, u64>, HashMap<&Rhs, u64>) = body
+            .iter()
+            .map(|(rhs, count)| (rhs, *count))
+            .partition(|(rhs, _)| matches!(rhs, Rhs::Terminal(_)));
+        let mut new_body = HashMap::new();
+        if !syntactic.is_empty() {
+            let share = syntactic.values().sum::<u64>() as f64 / total;
+            let distribution = match (
+                &pools,
+                smoothing.backoff,
+                backoff_chain(non_terminal, markers),
+            ) {
+                (Some(pools), Some(weight), Some(chain)) => {
+                    let siblings = siblings(non_terminal, markers);
+                    let fits = |rhs: &Rhs| fits_siblings(rhs, &siblings, markers);
+                    backoff(&syntactic, &chain, pools, weight, fits)
+                }
+                _ => {
+                    let mut lengths: Vec<usize> = syntactic
+                        .keys()
+                        .filter_map(|rhs| match rhs {
+                            Rhs::NonTerminals(children) => Some(children.len()),
+                            Rhs::Terminal(_) => None,
+                        })
+                        .collect();
+                    lengths.sort();
+                    lengths.dedup();
+                    let mut support: HashSet<&Rhs> = syntactic.keys().copied().collect();
+                    // without a pseudo count the unseen bodies would only get the weight 0
+                    if smoothing.rules.is_some_and(|alpha| alpha > 0.0) {
+                        for length in lengths {
+                            support.extend(bodies.get(&length).into_iter().flatten());
+                        }
+                    }
+                    let (mut distribution, unseen) =
+                        additive(&syntactic, support.len(), smoothing.rules);
+                    for rhs in support {
+                        distribution.entry(rhs).or_insert(unseen);
+                    }
+                    distribution
+                }
+            };
+            for (rhs, probability) in distribution {
+                new_body.insert((*rhs).clone(), share * probability);
+            }
+        }
+        if !lexical.is_empty() {
+            let share = lexical.values().sum::<u64>() as f64 / total;
+            let (distribution, unseen) = additive(&lexical, all_words.len(), smoothing.lexicon);
+            for (rhs, probability) in distribution {
+                new_body.insert((*rhs).clone(), share * probability);
+            }
+            if unseen > 0.0 && lexical.len() < all_words.len() {
+                new_body.entry(unknown.clone()).or_insert(share * unseen);
+            }
+        }
+        grammar.insert(non_terminal.clone(), new_body);
+    }
+    grammar
+}
+
+/// additive smoothing of `counts` over `support` right hand sides, which include the observed
+/// ones
+///
+/// returns the probabilities of the observed right hand sides and the probability of each unseen
+/// one, without a pseudo count this is the maximum likelihood estimate
+fn additive<'a>(
+    counts: &HashMap<&'a Rhs, u64>,
+    support: usize,
+    alpha: Option<f64>,
+) -> (HashMap<&'a Rhs, f64>, f64) {
+    let alpha = alpha.filter(|alpha| *alpha > 0.0).unwrap_or(0.0);
+    let total = counts.values().sum::<u64>() as f64 + alpha * support as f64;
+    let distribution = counts
+        .iter()
+        .map(|(rhs, count)| (*rhs, (*count as f64 + alpha) / total))
+        .collect();
+    (distribution, alpha / total)
+}
+
+/// returns the contexts of a markovised non-terminal `A|<B,C,D>` from the most to the least
+/// specific one: `A|<B,C,D>`, `A|<C,D>`, `A|<D>`
+///
+/// the parent and head annotations after the siblings are kept in every context
+/// returns `None` if the non-terminal is not markovised
+fn backoff_chain(non_terminal: &str, markers: &Markers) -> Option<Vec<String>> {
+    let label = strip_annotation(non_terminal);
+    let head = &non_terminal[label.len()..];
+    let annotation = markers.annotation(label)?;
+    let siblings = annotation.siblings.as_ref()?;
+    Some(
+        (0..siblings.len())
+            .map(|start| {
+                let context = Annotation {
+                    siblings: Some(siblings[start..].to_vec()),
+                    ..annotation.clone()
+                };
+                markers.format(&context) + head
+            })
+            .collect(),
+    )
+}
+
+/// the unescaped siblings of a markovised non-terminal
+fn siblings<'a>(non_terminal: &'a str, markers: &Markers) -> Vec<Cow<'a, str>> {
+    markers
+        .annotation(strip_annotation(non_terminal))
+        .and_then(|annotation| annotation.siblings)
+        .into_iter()
+        .flatten()
+        .map(|sibling| markers.unescape(sibling))
+        .collect()
+}
+
+/// the base label of a child that is not an intermediate node of the binarisation
+fn original_base<'a>(child: &'a str, markers: &Markers) -> Option<Cow<'a, str>> {
+    let label = strip_annotation(child);
+    match markers.annotation(label) {
+        Some(annotation) if annotation.siblings.is_some() => None,
+        _ => Some(markers.base(label)),
+    }
+}
+
+/// whether a right hand side can be produced by a markovised non-terminal with these siblings:
+/// the child that is not an intermediate node at either end has to be the next sibling at that
+/// end, `B A|<C,D>` fits `A|<B,C,D>` but `C A|<D>` does not
+fn fits_siblings(rhs: &Rhs, siblings: &[Cow<str>], markers: &Markers) -> bool {
+    let Rhs::NonTerminals(children) = rhs else {
+        return false;
+    };
+    let fits = |child: Option<&String>, sibling: Option<&Cow<str>>| {
+        child
+            .and_then(|child| original_base(child, markers))
+            .is_none_or(|base| Some(&base) == sibling)
+    };
+    fits(children.first(), siblings.first()) && fits(children.last(), siblings.last())
+}
+
+/// sums up the syntactic rule counts of all non-terminals that back off to the same context
+fn backoff_pools<'a>(
+    absolute_grammar: &'a HashMap<String, HashMap<Rhs, u64>>,
+    markers: &Markers,
+) -> HashMap<String, HashMap<&'a Rhs, u64>> {
+    let mut pools: HashMap<String, HashMap<&Rhs, u64>> = HashMap::new();
+    for (non_terminal, body) in absolute_grammar {
+        let Some(chain) = backoff_chain(non_terminal, markers) else {
+            continue;
+        };
+        for context in chain {
+            let pool = pools.entry(context).or_default();
+            for (rhs, count) in body {
+                if let Rhs::NonTerminals(_) = rhs {
+                    *pool.entry(rhs).or_default() += count;
+                }
+            }
+        }
+    }
+    pools
+}
+
+/// interpolates the counts of each context with the distribution of the next less specific
+/// context, where `weight` is the pseudo count given to the less specific distribution
+///
+/// only the pooled right hand sides that `fits` the non-terminal are used
+fn backoff<'a>(
+    counts: &HashMap<&'a Rhs, u64>,
+    chain: &[String],
+    pools: &HashMap<String, HashMap<&'a Rhs, u64>>,
+    weight: f64,
+    fits: impl Fn(&Rhs) -> bool,
+) -> HashMap<&'a Rhs, f64> {
+    let mut distribution: HashMap<&Rhs, f64> = HashMap::new();
+    for (level, context) in chain.iter().enumerate().rev() {
+        let counts: HashMap<&Rhs, u64> = if level == 0 {
+            counts.clone()
+        } else {
+            pools[context]
+                .iter()
+                .filter(|(rhs, _)| fits(rhs))
+                .map(|(rhs, count)| (*rhs, *count))
+                .collect()
+        };
+        if counts.is_empty() {
+            continue;
+        }
+        let total = counts.values().sum::<u64>() as f64;
+        let (total, weight) = if distribution.is_empty() {
+            (total, 0.0)
+        } else {
+            (total + weight, weight)
+        };
+        let mut next: HashMap<&Rhs, f64> = distribution
+            .iter()
+            .map(|(rhs, probability)| (*rhs, weight * probability / total))
+            .collect();
+        for (rhs, count) in counts {
+            *next.entry(rhs).or_default() += count as f64 / total;
+        }
+        distribution = next;
+    }
+    distribution
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::markers::DEFAULT_MARKERS;
+
+    fn body(rhs: &[&str]) -> Rhs {
+        Rhs::NonTerminals(rhs.iter().map(|e| e.to_string()).collect())
+    }
+
+    #[test]
+    fn backoff_chain_test() {
+        assert_eq!(backoff_chain("NP", &DEFAULT_MARKERS), None);
+        assert_eq!(
+            backoff_chain("NP|<DT,JJ,NN>^<S>", &DEFAULT_MARKERS),
+            Some(vec![
+                "NP|<DT,JJ,NN>^<S>".to_string(),
+                "NP|<JJ,NN>^<S>".to_string(),
+                "NP|<NN>^<S>".to_string(),
+            ])
+        );
+        assert_eq!(
+            backoff_chain("NP|<\\,,NN>[NN/dog]", &DEFAULT_MARKERS),
+            Some(vec![
+                "NP|<\\,,NN>[NN/dog]".to_string(),
+                "NP|<NN>[NN/dog]".to_string(),
+            ])
+        );
+        let markers = Markers::new("@", "#", ";", "!").unwrap();
+        assert_eq!(backoff_chain("NP|<DT,NN>", &markers), None);
+        assert_eq!(
+            backoff_chain("NP@DT;NN!#S!", &markers),
+            Some(vec!["NP@DT;NN!#S!".to_string(), "NP@NN!#S!".to_string()])
+        );
+    }
+
+    #[test]
+    fn additive_test() {
+        let grammar = HashMap::from_iter([
+            (
+                "A".to_string(),
+                HashMap::from_iter([(body(&["B", "C"]), 2), (Rhs::Terminal("a".to_string()), 2)]),
+            ),
+            (
+                "B".to_string(),
+                HashMap::from_iter([(body(&["C", "D"]), 1), (body(&["C"]), 1)]),
+            ),
+            (
+                "C".to_string(),
+                HashMap::from_iter([(Rhs::Terminal("b".to_string()), 1)]),
+            ),
+        ]);
+        let smoothing = Smoothing {
+            rules: Some(1.0),
+            lexicon: Some(1.0),
+            ..Default::default()
+        };
+        let smoothed = smooth_grammar(&grammar, &smoothing);
+        assert_eq!(smoothed["A"][&body(&["B", "C"])], 0.5 * 3.0 / 4.0);
+        assert_eq!(smoothed["A"][&body(&["C", "D"])], 0.5 * 1.0 / 4.0);
+        // unary rules are only smoothed for non-terminals that were seen with them
+        assert!(!smoothed["A"].contains_key(&body(&["C"])));
+        assert_eq!(smoothed["B"][&body(&["C", "D"])], 2.0 / 5.0);
+        assert_eq!(smoothed["B"][&body(&["C"])], 2.0 / 5.0);
+        assert_eq!(smoothed["B"][&body(&["B", "C"])], 1.0 / 5.0);
+        assert_eq!(smoothed["B"].len(), 3);
+        // the words that were not seen with a tag share the rule with UNK
+        assert_eq!(
+            smoothed["A"][&Rhs::Terminal("a".to_string())],
+            0.5 * 3.0 / 4.0
+        );
+        assert_eq!(
+            smoothed["A"][&Rhs::Terminal("UNK".to_string())],
+            0.5 * 1.0 / 4.0
+        );
+        assert!(!smoothed["A"].contains_key(&Rhs::Terminal("b".to_string())));
+        assert_eq!(smoothed["C"][&Rhs::Terminal("b".to_string())], 2.0 / 3.0);
+        assert_eq!(smoothed["C"][&Rhs::Terminal("UNK".to_string())], 1.0 / 3.0);
+
+        // only the lexicon is smoothed, so no rule gets the weight 0
+        let smoothing = Smoothing {
+            lexicon: Some(1.0),
+            ..Default::default()
+        };
+        let smoothed = smooth_grammar(&grammar, &smoothing);
+        assert!(smoothed.values().flat_map(|e| e.values()).all(|e| *e > 0.0));
+        assert!(!smoothed["A"].contains_key(&body(&["C", "D"])));
+        assert_eq!(smoothed["B"].len(), 2);
+    }
+
+    #[test]
+    fn backoff_test() {
+        let grammar = HashMap::from_iter([
+            (
+                "A|<B,C>".to_string(),
+                HashMap::from_iter([(body(&["B", "C"]), 1)]),
+            ),
+            (
+                "A|<D,C>".to_string(),
+                HashMap::from_iter([(body(&["D", "C"]), 3)]),
+            ),
+            (
+                "A|<B,B,C>".to_string(),
+                HashMap::from_iter([(body(&["B", "A|<B,C>"]), 2)]),
+            ),
+        ]);
+        let smoothing = Smoothing {
+            backoff: Some(1.0),
+            ..Default::default()
+        };
+        let smoothed = smooth_grammar(&grammar, &smoothing);
+        // A|<C> pools B C, D C and B A|<B,C>, only the ones starting with B fit A|<B,C>: B C 1/3
+        // and B A|<B,C> 2/3
+        assert_eq!(
+            smoothed["A|<B,C>"][&body(&["B", "C"])],
+            (1.0 + 1.0 / 3.0) / 2.0
+        );
+        assert_eq!(smoothed["A|<B,C>"][&body(&["B", "A|<B,C>"])], 1.0 / 3.0);
+        assert!(!smoothed["A|<B,C>"].contains_key(&body(&["D", "C"])));
+        assert_eq!(smoothed["A|<D,C>"][&body(&["D", "C"])], 1.0);
+        assert_eq!(smoothed["A|<D,C>"].len(), 1);
+    }
+}
@@ -1,13 +1,21 @@
-use std::fmt::Display;
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    fmt::Display,
+    fs::File,
+    io::{BufRead, BufReader, Lines},
+    path::Path,
+    process::exit,
+};
 
 use nom::{
     IResult, Parser,
     branch::alt,
     bytes::complete::is_not,
-    character::complete::{char, space0},
-    combinator::map,
-    multi::many1,
-    sequence::delimited,
+    character::complete::{anychar, char, space0},
+    combinator::{map, recognize},
+    multi::{many1, many1_count},
+    sequence::{delimited, preceded},
 };
 
 #[derive(Debug, PartialEq, Eq, Default)]
@@ -68,8 +76,104 @@ impl<T: Display> Display for ParseTree<T> {
     }
 }
 
+/// characters that end a token unless they are escaped by a backslash
+const SPECIAL: &str = " \t()\\";
+
+/// a token in its escaped form, e.g. `\(` for a literal bracket or `New\ York` for a token with
+/// a space
+pub fn token(input: &str) -> IResult<&str, &str> {
+    recognize(many1_count(alt((
+        is_not(SPECIAL),
+        recognize(preceded(char('\\'), anychar)),
+    ))))
+    .parse(input)
+}
+
 pub fn atom(input: &str) -> IResult<&str, &str> {
-    delimited(space0, is_not(" ()"), space0).parse(input)
+    delimited(space0, token, space0).parse(input)
+}
+
+/// returns the escaped form of a token as it is written in trees and grammar files
+pub fn escape(token: &str) -> Cow<'_, str> {
+    if !token.contains(|c| SPECIAL.contains(c)) {
+        return Cow::Borrowed(token);
+    }
+    let mut escaped = String::with_capacity(token.len() + 2);
+    for c in token.chars() {
+        if SPECIAL.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    Cow::Owned(escaped)
+}
+
+/// returns the literal token of an escaped token
+pub fn unescape(token: &str) -> Cow<'_, str> {
+    if !token.contains('\\') {
+        return Cow::Borrowed(token);
+    }
+    let mut unescaped = String::with_capacity(token.len());
+    let mut chars = token.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    Cow::Owned(unescaped)
+}
+
+/// splits a sentence at unescaped whitespace into tokens in their canonical escaped form
+///
+/// tokens may be given literally like `(` or escaped like `\(`, both become `\(`
+pub fn sentence_tokens(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c.is_whitespace() {
+            if let Some(start) = start.take() {
+                tokens.push(escape(&unescape(&line[start..i])).into_owned());
+            }
+            continue;
+        } else if c == '\\' {
+            escaped = true;
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(start) = start {
+        tokens.push(escape(&unescape(&line[start..])).into_owned());
+    }
+    tokens
+}
+
+/// the Penn Treebank names of bracket tokens and the escaped brackets they stand for
+pub const BRACKET_TOKENS: [(&str, &str); 6] = [
+    ("-LRB-", "\\("),
+    ("-RRB-", "\\)"),
+    ("-LSB-", "["),
+    ("-RSB-", "]"),
+    ("-LCB-", "{"),
+    ("-RCB-", "}"),
+];
+
+/// replaces the words `-LRB-`, `-RRB-` … of the tree by the brackets they stand for, the tags
+/// are left as they are
+pub fn restore_brackets<'a, T: AsRef<str> + From<&'a str>>(tree: &mut ParseTree<T>) {
+    tree.change_nodes(&mut |node| {
+        if !node.is_leaf() {
+            return;
+        }
+        if let Some((_, bracket)) = BRACKET_TOKENS
+            .iter()
+            .find(|(name, _)| *name == node.root.as_ref())
+        {
+            node.root = T::from(bracket);
+        }
+    });
 }
 
 fn str_to_parsetree_vec(input: &str) -> Vec<ParseTree<&str>> {
@@ -104,6 +208,214 @@ pub fn element(input: &str) -> IResult<&str, ParseTree<&str>> {
     ))
 }
 
+/// like [`element`] but also accepts the unlabelled outer brackets of the Penn Treebank
+/// `( (S …))`, which become a root with the empty label
+pub fn treebank_element(input: &str) -> IResult<&str, ParseTree<&str>> {
+    alt((
+        element,
+        map(
+            delimited(space0, delimited(char('('), element, char(')')), space0),
+            |tree| ParseTree {
+                root: "",
+                children: vec![tree],
+            },
+        ),
+    ))
+    .parse(input)
+}
+
+/// the text of one tree and the line and column (both starting at 1) where it starts
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TreeText {
+    pub text: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ReadError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl TreeText {
+    pub fn error(&self, message: &str) -> ReadError {
+        ReadError {
+            line: self.line,
+            column: self.column,
+            message: message.to_string(),
+        }
+    }
+
+    /// parses the tree, the unlabelled outer brackets of the Penn Treebank become the root
+    /// `ROOT` like in `transform`
+    pub fn parse(&self) -> Result<ParseTree<&str>, ReadError> {
+        match treebank_element(&self.text) {
+            Ok((remaining, mut tree)) if remaining.trim().is_empty() => {
+                if tree.root.is_empty() {
+                    tree.root = "ROOT";
+                }
+                Ok(tree)
+            }
+            _ => Err(self.error("malformed tree")),
+        }
+    }
+
+    /// parses the tree or reports where the malformed tree starts in `source` and exits
+    pub fn parse_or_exit(&self, source: &str) -> ParseTree<&str> {
+        self.parse().unwrap_or_else(|e| {
+            eprintln!("{}, {}", source, e);
+            exit(1);
+        })
+    }
+}
+
+/// reads trees that are written one per line or spread over several lines like in the `.mrg`
+/// files of the Penn Treebank
+///
+/// blank lines and `*x*` comment headers between trees are skipped
+pub struct TreeReader<R> {
+    lines: Lines<R>,
+    line_number: usize,
+    depth: usize,
+    /// the previous character was an unescaped backslash
+    escaped: bool,
+    current: Option<TreeText>,
+    pending: VecDeque<Result<TreeText, ReadError>>,
+}
+
+impl<R: BufRead> TreeReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            line_number: 0,
+            depth: 0,
+            escaped: false,
+            current: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn read_line(&mut self, line: &str) {
+        if self.depth == 0 && (line.trim().is_empty() || line.starts_with("*x*")) {
+            return;
+        }
+        if let Some(current) = &mut self.current {
+            current.text.push(' ');
+        }
+        self.escaped = false;
+        for (column, c) in line.chars().enumerate() {
+            if self.depth == 0 {
+                if c.is_whitespace() {
+                    continue;
+                }
+                if c != '(' {
+                    self.pending.push_back(Err(ReadError {
+                        line: self.line_number,
+                        column: column + 1,
+                        message: format!("unexpected {:?} outside of a tree", c),
+                    }));
+                    return;
+                }
+                self.current = Some(TreeText {
+                    text: String::new(),
+                    line: self.line_number,
+                    column: column + 1,
+                });
+            }
+            let current = self.current.as_mut().expect("a tree is open");
+            current.text.push(c);
+            if self.escaped {
+                self.escaped = false;
+                continue;
+            }
+            match c {
+                '\\' => self.escaped = true,
+                '(' => self.depth += 1,
+                ')' => {
+                    self.depth -= 1;
+                    if self.depth == 0 {
+                        self.pending.push_back(Ok(self.current.take().unwrap()));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TreeReader<R> {
+    type Item = Result<TreeText, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            match self.lines.next() {
+                Some(Ok(line)) => {
+                    self.line_number += 1;
+                    self.read_line(&line);
+                }
+                Some(Err(e)) => {
+                    return Some(Err(ReadError {
+                        line: self.line_number + 1,
+                        column: 1,
+                        message: e.to_string(),
+                    }));
+                }
+                None => {
+                    let current = self.current.take()?;
+                    self.depth = 0;
+                    return Some(Err(current.error("tree is not closed")));
+                }
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// reads the trees of `reader`, errors are reported with their position in `source` and exit
+pub fn read_trees<R: BufRead>(reader: R, source: &str) -> impl Iterator<Item = TreeText> + use<R> {
+    let source = source.to_string();
+    TreeReader::new(reader).map(move |tree| {
+        tree.unwrap_or_else(|e| {
+            eprintln!("{}, {}", source, e);
+            exit(1);
+        })
+    })
+}
+
+/// reads the trees of the file at `path` like [`read_trees`], exits if it cannot be opened
+pub fn open_trees(path: &Path) -> impl Iterator<Item = TreeText> + use<> {
+    let Ok(file) = File::open(path) else {
+        eprintln!("cannot open {}", path.display());
+        exit(1);
+    };
+    read_trees(BufReader::new(file), &path.display().to_string())
+}
+
+/// reads the file at `path` with `read`, exits with a message that names the file as `what` if
+/// it cannot be opened or with the error of `read`
+pub fn read_or_exit<T>(
+    path: &Path,
+    what: &str,
+    read: impl FnOnce(BufReader<File>) -> Result<T, String>,
+) -> T {
+    let Ok(file) = File::open(path) else {
+        eprintln!("cannot open {} {}", what, path.display());
+        exit(1);
+    };
+    read(BufReader::new(file)).unwrap_or_else(|message| {
+        eprintln!("{}: {}", path.display(), message);
+        exit(1);
+    })
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -118,6 +430,34 @@ mod tests {
         assert!(atom("(hallo").is_err());
         assert_eq!(atom(" \t hallo  "), Ok(("", "hallo")));
         assert_eq!(many1(atom).parse("hallo hi"), Ok(("", vec!["hallo", "hi"])));
+        assert_eq!(atom("\\( a"), Ok(("a", "\\(")));
+        assert_eq!(atom("New\\ York)"), Ok((")", "New\\ York")));
+        assert_eq!(atom("a\tb"), Ok(("b", "a")));
+    }
+
+    #[test]
+    fn escape_test() {
+        for token in ["(", ")", "a b", "a\tb", "\\", "plain", ":-)"] {
+            let escaped = escape(token);
+            assert_eq!(atom(&escaped), Ok(("", &escaped[..])));
+            assert_eq!(unescape(&escaped), token);
+        }
+        assert_eq!(escape("(").as_ref(), "\\(");
+        assert_eq!(
+            sentence_tokens(" ( \\) New\\ York  a\\b"),
+            vec!["\\(", "\\)", "New\\ York", "ab"]
+        );
+
+        let line = "(S (-LRB- -LRB-) (NP (NN \\)) (NN a\\ b)) (-RRB- -RRB-))";
+        let trees: Vec<_> = TreeReader::new(line.as_bytes()).collect();
+        assert_eq!(trees.len(), 1);
+        let mut tree = trees[0].as_ref().unwrap().parse().unwrap();
+        assert_eq!(tree.to_string(), line);
+        restore_brackets(&mut tree);
+        assert_eq!(
+            tree.to_string(),
+            "(S (-LRB- \\() (NP (NN \\)) (NN a\\ b)) (-RRB- \\)))"
+        );
     }
 
     #[test]
@@ -146,6 +486,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tree_reader_test() {
+        let input = "*x* header\n\n( (S\n    (NP (DT the))\n    (VP ran)) )\n(A a) (B b)\n";
+        let trees: Vec<_> = TreeReader::new(input.as_bytes()).collect();
+        assert_eq!(trees.len(), 3);
+        let first = trees[0].as_ref().unwrap();
+        assert_eq!((first.line, first.column), (3, 1));
+        assert_eq!(
+            treebank_element(&first.text).unwrap().1.to_string(),
+            "( (S (NP (DT the)) (VP ran)))"
+        );
+        let third = trees[2].as_ref().unwrap();
+        assert_eq!((third.line, third.column), (6, 7));
+        assert_eq!(third.parse().unwrap().to_string(), "(B b)");
+
+        let trees: Vec<_> = TreeReader::new("(A a)\n  (B (C c)\n".as_bytes()).collect();
+        assert_eq!(trees[1].as_ref().unwrap_err().to_string(), "line 2, column 3: tree is not closed");
+        let trees: Vec<_> = TreeReader::new("(A a))".as_bytes()).collect();
+        assert_eq!(trees[1].as_ref().unwrap_err().column, 6);
+        let tree = TreeReader::new(" (A (B))".as_bytes()).next().unwrap().unwrap();
+        assert_eq!(tree.parse().unwrap_err().to_string(), "line 1, column 2: malformed tree");
+    }
+
+    #[test]
+    fn treebank_element_test() {
+        let (remaining, tree) = treebank_element("( (S (NP hi)) )").unwrap();
+        assert_eq!(remaining, "");
+        assert_eq!(tree.root, "");
+        assert_eq!(tree.to_string(), "( (S (NP hi)))");
+        assert!(treebank_element("( (S (NP hi)) (S (NP hi)))").is_err());
+
+        let mut trees = TreeReader::new("( (S (NP hi))\n  )".as_bytes());
+        let tree_text = trees.next().unwrap().unwrap();
+        assert_eq!(tree_text.parse().unwrap().to_string(), "(ROOT (S (NP hi)))");
+    }
+
     #[test]
     fn element_test() {
         assert!(element("(ROOT test test)").is_err());
@@ -0,0 +1,242 @@
+use std::{
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use foldhash::{HashMap, HashMapExt};
+use nom::{Parser, multi::many0};
+
+use super::{
+    Rhs,
+    parse_tree::{atom, escape, read_or_exit, unescape},
+};
+
+/// the longest suffix that is used, as in TnT
+const MAX_SUFFIX: usize = 10;
+
+/// TnT-style model of the tags of unknown words given their last letters (Brants 2000)
+///
+/// it is estimated from the rare words of the training data, P(t | suffix) is smoothed with
+/// successive abstraction over the shorter suffixes and turned into the weight of the lexical
+/// rule `t -> w` with P(w | t) = P(t | suffix) P(w) / P(t)
+#[derive(Debug, Default, PartialEq)]
+pub struct SuffixModel {
+    /// tag counts of the rare words for every suffix, the empty suffix counts all rare words
+    counts: HashMap<String, HashMap<String, u64>>,
+}
+
+impl SuffixModel {
+    /// estimates the model from the words that occur at most `threshold` times
+    pub fn train(absolute_grammar: &HashMap<String, HashMap<Rhs, u64>>, threshold: u64) -> Self {
+        let mut word_counts: HashMap<&str, u64> = HashMap::new();
+        for body in absolute_grammar.values() {
+            for (rhs, count) in body {
+                if let Rhs::Terminal(word) = rhs {
+                    *word_counts.entry(word).or_default() += count;
+                }
+            }
+        }
+        let mut model = Self::default();
+        for (tag, body) in absolute_grammar {
+            for (rhs, count) in body {
+                match rhs {
+                    Rhs::Terminal(word) if word_counts[&word[..]] <= threshold => {
+                        model.add(word, tag, *count)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        model
+    }
+
+    fn add(&mut self, word: &str, tag: &str, count: u64) {
+        for suffix in suffixes(word) {
+            *self
+                .counts
+                .entry(suffix)
+                .or_default()
+                .entry(tag.to_string())
+                .or_default() += count;
+        }
+    }
+
+    /// writes one `TAG COUNT SUFFIX` line per suffix and tag, the suffix is left out for the
+    /// counts of all rare words
+    pub fn write(&self, output: &mut impl Write) {
+        let mut lines: Vec<(&str, &str, u64)> = self
+            .counts
+            .iter()
+            .flat_map(|(suffix, tags)| {
+                tags.iter()
+                    .map(move |(tag, count)| (&suffix[..], &tag[..], *count))
+            })
+            .collect();
+        lines.sort();
+        for (suffix, tag, count) in lines {
+            if suffix.is_empty() {
+                writeln!(output, "{} {}", tag, count)
+            } else {
+                writeln!(output, "{} {} {}", tag, count, escape(suffix))
+            }
+            .expect("cannot write to the suffix model");
+        }
+    }
+
+    pub fn read(reader: impl BufRead) -> Result<Self, String> {
+        let mut model = Self::default();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let malformed = || format!("line {} is malformed: {:?}", number + 1, line);
+            let (_, tokens) = many0(atom).parse(&line).map_err(|_| malformed())?;
+            let (tag, count, suffix) = match tokens[..] {
+                [tag, count] => (tag, count, ""),
+                [tag, count, suffix] => (tag, count, suffix),
+                _ => return Err(malformed()),
+            };
+            let count = count.parse::<u64>().map_err(|_| malformed())?;
+            *model
+                .counts
+                .entry(unescape(suffix).into_owned())
+                .or_default()
+                .entry(tag.to_string())
+                .or_default() += count;
+        }
+        Ok(model)
+    }
+
+    pub fn from_file(path: &Path) -> Self {
+        read_or_exit(path, "suffix model", Self::read)
+    }
+
+    /// the tags of the rare words
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.counts
+            .get("")
+            .into_iter()
+            .flatten()
+            .map(|(tag, _)| &tag[..])
+    }
+
+    /// returns the weights of the lexical rules `tag -> word` for an unknown word, sorted by tag
+    pub fn tag_weights(&self, word: &str) -> Vec<(&str, f64)> {
+        let Some(tags) = self.counts.get("") else {
+            return Vec::new();
+        };
+        let total: u64 = tags.values().sum();
+        let priors: Vec<(&str, f64)> = tags
+            .iter()
+            .map(|(tag, count)| (&tag[..], *count as f64 / total as f64))
+            .collect();
+        let theta = theta(&priors);
+
+        // successive abstraction from the empty suffix to the longest one that was seen
+        let mut probabilities: Vec<f64> = priors.iter().map(|(_, prior)| *prior).collect();
+        for suffix in suffixes(word).iter().skip(1) {
+            let Some(counts) = self.counts.get(suffix) else {
+                break;
+            };
+            let suffix_total: u64 = counts.values().sum();
+            for ((tag, _), probability) in priors.iter().zip(&mut probabilities) {
+                let observed = counts.get(*tag).copied().unwrap_or(0) as f64 / suffix_total as f64;
+                *probability = (observed + theta * *probability) / (1.0 + theta);
+            }
+        }
+
+        // P(w) is the same for every tag and does not change the best parse, it is taken as
+        // the probability of one rare word token
+        let word_probability = 1.0 / total as f64;
+        let mut weights: Vec<(&str, f64)> = priors
+            .iter()
+            .zip(probabilities)
+            .map(|((tag, prior), probability)| (*tag, probability * word_probability / prior))
+            .collect();
+        weights.sort_by(|a, b| a.0.cmp(b.0));
+        weights
+    }
+}
+
+/// the weight of the shorter suffix in successive abstraction: the sample standard deviation of
+/// the tag probabilities
+fn theta(priors: &[(&str, f64)]) -> f64 {
+    if priors.len() < 2 {
+        return 0.0;
+    }
+    let mean = 1.0 / priors.len() as f64;
+    let variance = priors
+        .iter()
+        .map(|(_, prior)| (prior - mean).powi(2))
+        .sum::<f64>()
+        / (priors.len() - 1) as f64;
+    variance.sqrt()
+}
+
+/// the suffixes of the word from the empty one to the longest used one
+fn suffixes(word: &str) -> Vec<String> {
+    let chars: Vec<char> = unescape(word).chars().collect();
+    (0..=chars.len().min(MAX_SUFFIX))
+        .map(|length| chars[chars.len() - length..].iter().collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn grammar() -> HashMap<String, HashMap<Rhs, u64>> {
+        let lexical = |entries: &[(&str, u64)]| {
+            entries
+                .iter()
+                .map(|(word, count)| (Rhs::Terminal(word.to_string()), *count))
+                .collect()
+        };
+        HashMap::from_iter([
+            (
+                "VBG".to_string(),
+                lexical(&[("walking", 1), ("talking", 1), ("being", 5)]),
+            ),
+            ("NN".to_string(), lexical(&[("king", 1), ("table", 1)])),
+            ("NNS".to_string(), lexical(&[("tables", 1)])),
+        ])
+    }
+
+    #[test]
+    fn tag_weights_test() {
+        let model = SuffixModel::train(&grammar(), 1);
+        let weights = model.tag_weights("singing");
+        assert_eq!(
+            weights.iter().map(|(tag, _)| *tag).collect::<Vec<_>>(),
+            vec!["NN", "NNS", "VBG"]
+        );
+        let weight = |tag| weights.iter().find(|e| e.0 == tag).unwrap().1;
+        assert!(weight("VBG") > weight("NN"));
+        assert!(weight("NN") > weight("NNS"));
+        let weights = model.tag_weights("chairs");
+        assert!(weights[1].1 > weights[0].1);
+        assert!(SuffixModel::default().tag_weights("singing").is_empty());
+    }
+
+    #[test]
+    fn theta_test() {
+        // the standard deviation of 0.75 and 0.25 around their mean 0.5
+        assert!((theta(&[("NN", 0.75), ("VB", 0.25)]) - 0.125f64.sqrt()).abs() < 1e-12);
+        assert_eq!(theta(&[("NN", 0.5), ("VB", 0.5)]), 0.0);
+        assert_eq!(theta(&[("NN", 1.0)]), 0.0);
+    }
+
+    #[test]
+    fn read_write_test() {
+        let mut model = SuffixModel::train(&grammar(), 1);
+        model.add("a\\ b", "NN", 2);
+        let mut output = Vec::new();
+        model.write(&mut output);
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("NN 2 \\ b\n"));
+        assert!(text.starts_with("NN 4\n"));
+        assert_eq!(SuffixModel::read(text.as_bytes()).unwrap(), model);
+        assert!(SuffixModel::read("NN x ing".as_bytes()).is_err());
+    }
+}
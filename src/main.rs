@@ -4,25 +4,82 @@ mod induce;
 mod parse;
 mod unk;
 mod binarise;
+mod random;
 mod smoothing;
+mod split_merge;
 mod astar;
+mod em;
+mod head;
+mod eval;
+mod json;
+mod compare;
+mod transform;
+mod output;
+mod show;
+mod markers;
+mod verify;
+mod normalisation;
 
 
 use argparse::{Args, Commands};
 use clap::Parser;
 use debinarise::debinarise;
+use compare::compare;
+use em::train_em;
+use eval::eval;
+use head::{HeadRules, Lexicalisation};
 use binarise::binarise;
-use induce::induce;
-use parse::parse;
+use induce::{estimate::Smoothing, induce};
+use parse::{UnknownWords, parse};
+use show::show;
 use smoothing::smooth;
+use split_merge::split_merge;
+use transform::transform;
 use unk::unk;
+use verify::verify_transform;
 use astar::out;
 
 fn main() {
     let args = Args::parse();
     match &args.command {
-        Commands::Induce { grammar } => {
-            induce(grammar);
+        Commands::Induce {
+            grammar,
+            counts,
+            suffix_model,
+            add,
+            input,
+            rule_smoothing,
+            lexicon_smoothing,
+            backoff,
+            lexicalise,
+            head_rules,
+            lexical_threshold,
+            markers,
+        } => {
+            let markers = markers.markers();
+            let smoothing = Smoothing {
+                rules: *rule_smoothing,
+                lexicon: *lexicon_smoothing,
+                backoff: *backoff,
+                markers: markers.clone(),
+            };
+            let lexicalisation = lexicalise.map(|mode| Lexicalisation {
+                mode,
+                head_rules: head_rules
+                    .as_ref()
+                    .map_or_else(HeadRules::collins, |path| HeadRules::from_file(path)),
+                threshold: *lexical_threshold,
+                markers,
+            });
+            induce(
+                grammar,
+                *counts,
+                *suffix_model,
+                add,
+                input,
+                &smoothing,
+                lexicalisation.as_ref(),
+            );
         }
 
         Commands::Parse {
@@ -32,42 +89,152 @@ fn main() {
             initial_nonterminal,
             unking,
             smoothing,
+            signature,
+            suffix_model,
+            embeddings,
+            neighbours,
+            normalise,
+            edit_distance,
+            verbose,
             threshold_beam,
             rank_beam,
             astar,
+            lexicalised,
+            lexical_backoff,
+            latent,
+            restore_brackets,
+            output,
         } => {
+            let unknown = UnknownWords {
+                unking: *unking,
+                smoothing: smoothing.then(|| signature.scheme()),
+                suffix_model: suffix_model.clone(),
+                embeddings: embeddings.clone(),
+                neighbours: *neighbours,
+                normalisation: normalise.then_some(*edit_distance),
+                verbose: *verbose,
+            };
             parse(
                 rules,
                 lexicon,
                 paradigma,
                 initial_nonterminal,
-                unking,
-                smoothing,
+                &unknown,
                 threshold_beam,
                 rank_beam,
                 astar,
+                lexicalised.then_some(*lexical_backoff),
+                latent,
+                restore_brackets,
+                output.output_format,
             );
         }
 
-        Commands::Unk { threshold } => {
-            unk(*threshold);
+        Commands::TrainEm {
+            rules,
+            lexicon,
+            grammar,
+            initial_nonterminal,
+            iterations,
+            unking,
+            smoothing,
+            signature,
+        } => {
+            train_em(
+                rules,
+                lexicon,
+                grammar,
+                initial_nonterminal,
+                *iterations,
+                unking,
+                smoothing.then(|| signature.scheme()).as_ref(),
+            );
         }
 
-        Commands::Debinarise {} => {
-            debinarise();
+        Commands::SplitMerge {
+            rules,
+            lexicon,
+            grammar,
+            initial_nonterminal,
+            cycles,
+            iterations,
+            merge_fraction,
+            seed,
+        } => {
+            split_merge(
+                rules,
+                lexicon,
+                grammar,
+                initial_nonterminal,
+                *cycles,
+                *iterations,
+                *merge_fraction,
+                *seed,
+            );
+        }
+
+        Commands::Unk { threshold, vocabulary, inputs } => {
+            unk(*threshold, vocabulary, inputs);
+        }
+
+        Commands::Debinarise { restore_brackets, markers, output } => {
+            debinarise(*restore_brackets, &markers.markers(), output.output_format);
         }
 
-        Commands::Binarise { horizontal, vertical } => {
-            binarise(*horizontal, *vertical);
+        Commands::Transform { steps, root, restore_brackets, output } => {
+            transform(steps, root, *restore_brackets, output.output_format);
         }
 
-        Commands::Smooth { threshold } => {
-            smooth(*threshold);
+        Commands::Binarise { binarisation, output } => {
+            binarise(&binarisation.binarisation(), output.output_format);
+        }
+
+        Commands::Smooth {
+            threshold,
+            signature,
+            vocabulary,
+            inputs,
+        } => {
+            smooth(*threshold, vocabulary, inputs, &signature.scheme());
         }
 
         Commands::Outside { rules, lexicon, grammar, initial_nonterminal } => {
             out(rules, lexicon, grammar, initial_nonterminal);
 
         }
+
+        Commands::Eval { gold, predicted, parameters, breakdown } => {
+            eval(gold, predicted, parameters, *breakdown);
+        }
+
+        Commands::VerifyTransform { binarisation } => {
+            verify_transform(&binarisation.binarisation());
+        }
+
+        Commands::Show { gold, ascii } => {
+            show(gold, *ascii);
+        }
+
+        Commands::Compare {
+            gold,
+            first,
+            second,
+            parameters,
+            method,
+            samples,
+            confidence,
+            seed,
+        } => {
+            compare(
+                gold,
+                first,
+                second,
+                parameters,
+                *method,
+                *samples,
+                *confidence,
+                *seed,
+            );
+        }
     }
 }
@@ -0,0 +1,344 @@
+use std::{borrow::Cow, process::exit};
+
+use clap::Args;
+
+/// the syntax of the annotations that `binarise` adds to labels: `X|<A,B>^<P,Q>` has the
+/// siblings `A,B` of the horizontal markovisation and the parents `P,Q` of the vertical one
+///
+/// characters of the original labels that start a marker are escaped with a backslash, so the
+/// annotations are found again even if the labels contain `|`, `^`, `,` or `>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Markers {
+    pub horizontal: Cow<'static, str>,
+    pub vertical: Cow<'static, str>,
+    pub separator: Cow<'static, str>,
+    pub close: Cow<'static, str>,
+}
+
+pub const DEFAULT_MARKERS: Markers = Markers {
+    horizontal: Cow::Borrowed("|<"),
+    vertical: Cow::Borrowed("^<"),
+    separator: Cow::Borrowed(","),
+    close: Cow::Borrowed(">"),
+};
+
+impl Default for Markers {
+    fn default() -> Self {
+        DEFAULT_MARKERS
+    }
+}
+
+// the marker options of `binarise`, `debinarise` and `induce`
+#[derive(Args, Clone, Debug)]
+pub struct MarkerOptions {
+    /// starts the siblings of a markovised label
+    #[arg(long, default_value_t = String::from("|<"))]
+    pub horizontal_marker: String,
+    /// starts the parents of a parent annotated label
+    #[arg(long, default_value_t = String::from("^<"))]
+    pub vertical_marker: String,
+    /// separates the siblings and the parents
+    #[arg(long, default_value_t = String::from(","))]
+    pub separator: String,
+    /// ends the siblings and the parents
+    #[arg(long, default_value_t = String::from(">"))]
+    pub close_marker: String,
+}
+
+impl MarkerOptions {
+    pub fn markers(&self) -> Markers {
+        Markers::new(
+            &self.horizontal_marker,
+            &self.vertical_marker,
+            &self.separator,
+            &self.close_marker,
+        )
+        .unwrap_or_else(|message| {
+            eprintln!("{}", message);
+            exit(1);
+        })
+    }
+}
+
+/// a label split into its parts, all parts are still escaped
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Annotation<'a> {
+    pub base: &'a str,
+    pub siblings: Option<Vec<&'a str>>,
+    pub parents: Option<Vec<&'a str>>,
+}
+
+#[derive(PartialEq, Eq)]
+enum State {
+    Base,
+    Siblings,
+    AfterSiblings,
+    Parents,
+    End,
+}
+
+impl Markers {
+    pub fn new(
+        horizontal: &str,
+        vertical: &str,
+        separator: &str,
+        close: &str,
+    ) -> Result<Self, String> {
+        let markers = [horizontal, vertical, separator, close];
+        for (i, marker) in markers.iter().enumerate() {
+            let Some(first) = marker.chars().next() else {
+                return Err("markers must not be empty".to_string());
+            };
+            if marker.contains(|c: char| c.is_whitespace() || "()\\".contains(c)) {
+                return Err(format!(
+                    "marker {:?} must not contain whitespace, brackets or backslashes",
+                    marker
+                ));
+            }
+            if marker[first.len_utf8()..].starts_with(first) {
+                return Err(format!(
+                    "marker {:?} must not repeat its first character",
+                    marker
+                ));
+            }
+            if markers
+                .iter()
+                .enumerate()
+                .any(|(j, other)| i != j && other.starts_with(marker))
+            {
+                return Err(format!(
+                    "marker {:?} is the start of another marker",
+                    marker
+                ));
+            }
+        }
+        Ok(Self {
+            horizontal: Cow::Owned(horizontal.to_string()),
+            vertical: Cow::Owned(vertical.to_string()),
+            separator: Cow::Owned(separator.to_string()),
+            close: Cow::Owned(close.to_string()),
+        })
+    }
+
+    fn markers(&self) -> [&str; 4] {
+        [
+            &self.horizontal,
+            &self.vertical,
+            &self.separator,
+            &self.close,
+        ]
+    }
+
+    fn is_special(&self, c: char) -> bool {
+        c == '\\' || self.markers().iter().any(|marker| marker.starts_with(c))
+    }
+
+    /// escapes the characters of an original label that could be read as a marker
+    pub fn escape<'a>(&self, label: &'a str) -> Cow<'a, str> {
+        if !label.contains(|c| self.is_special(c)) {
+            return Cow::Borrowed(label);
+        }
+        let mut escaped = String::with_capacity(label.len() + 2);
+        for c in label.chars() {
+            if self.is_special(c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        Cow::Owned(escaped)
+    }
+
+    /// returns the original label of an escaped label
+    pub fn unescape<'a>(&self, label: &'a str) -> Cow<'a, str> {
+        if !label.contains('\\') {
+            return Cow::Borrowed(label);
+        }
+        let mut unescaped = String::with_capacity(label.len());
+        let mut chars = label.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => unescaped.extend(chars.next()),
+                c => unescaped.push(c),
+            }
+        }
+        Cow::Owned(unescaped)
+    }
+
+    /// splits a label written by [`Markers::format`] into its parts
+    ///
+    /// returns `None` for labels that `binarise` cannot have produced, e.g. with unescaped marker
+    /// characters outside of the annotations
+    pub fn annotation<'a>(&self, label: &'a str) -> Option<Annotation<'a>> {
+        let mut state = State::Base;
+        let mut base = label;
+        let mut siblings = None;
+        let mut parents = None;
+        let mut list = Vec::new();
+        let mut item_start = 0;
+        let mut i = 0;
+        while i < label.len() {
+            let rest = &label[i..];
+            let c = rest.chars().next().unwrap();
+            if c == '\\' {
+                let escaped = rest[1..].chars().next()?;
+                if matches!(state, State::AfterSiblings | State::End) {
+                    return None;
+                }
+                i += 1 + escaped.len_utf8();
+                continue;
+            }
+            let marker = |marker: &str| rest.starts_with(marker).then_some(marker.len());
+            match state {
+                State::Base | State::AfterSiblings => {
+                    if let Some(length) = marker(&self.horizontal).filter(|_| state == State::Base)
+                    {
+                        base = &label[..i];
+                        state = State::Siblings;
+                        i += length;
+                        item_start = i;
+                        continue;
+                    }
+                    if let Some(length) = marker(&self.vertical) {
+                        if state == State::Base {
+                            base = &label[..i];
+                        }
+                        state = State::Parents;
+                        i += length;
+                        item_start = i;
+                        continue;
+                    }
+                    if self.is_special(c) || state == State::AfterSiblings {
+                        return None;
+                    }
+                }
+                State::Siblings | State::Parents => {
+                    if let Some(length) = marker(&self.close) {
+                        list.push(&label[item_start..i]);
+                        if state == State::Siblings {
+                            siblings = Some(std::mem::take(&mut list));
+                            state = State::AfterSiblings;
+                        } else {
+                            parents = Some(std::mem::take(&mut list));
+                            state = State::End;
+                        }
+                        i += length;
+                        continue;
+                    }
+                    if let Some(length) = marker(&self.separator) {
+                        list.push(&label[item_start..i]);
+                        i += length;
+                        item_start = i;
+                        continue;
+                    }
+                    if self.is_special(c) {
+                        return None;
+                    }
+                }
+                State::End => return None,
+            }
+            i += c.len_utf8();
+        }
+        if matches!(state, State::Siblings | State::Parents) {
+            return None;
+        }
+        Some(Annotation {
+            base,
+            siblings,
+            parents,
+        })
+    }
+
+    /// writes the label `base|<siblings>^<parents>` from escaped parts
+    pub fn format(&self, annotation: &Annotation) -> String {
+        let mut label = annotation.base.to_string();
+        if let Some(siblings) = &annotation.siblings {
+            label += &self.horizontal;
+            label += &siblings.join(&self.separator);
+            label += &self.close;
+        }
+        if let Some(parents) = &annotation.parents {
+            label += &self.vertical;
+            label += &parents.join(&self.separator);
+            label += &self.close;
+        }
+        label
+    }
+
+    /// returns the original label without annotations, labels that `binarise` cannot have
+    /// produced are returned as they are
+    pub fn base<'a>(&self, label: &'a str) -> Cow<'a, str> {
+        match self.annotation(label) {
+            Some(annotation) => self.unescape(annotation.base),
+            None => Cow::Borrowed(label),
+        }
+    }
+
+    /// returns the original label of an intermediate node `X|<…>` of the binarisation
+    pub fn intermediate<'a>(&self, label: &'a str) -> Option<Cow<'a, str>> {
+        let annotation = self.annotation(label)?;
+        annotation.siblings.as_ref()?;
+        Some(self.unescape(annotation.base))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn annotation_test() {
+        let markers = Markers::default();
+        assert_eq!(
+            markers.annotation("FRAG|<NP-TMP,.>^<ROOT,S>"),
+            Some(Annotation {
+                base: "FRAG",
+                siblings: Some(vec!["NP-TMP", "."]),
+                parents: Some(vec!["ROOT", "S"]),
+            })
+        );
+        assert_eq!(
+            markers.annotation("S^<ROOT>"),
+            Some(Annotation {
+                base: "S",
+                siblings: None,
+                parents: Some(vec!["ROOT"]),
+            })
+        );
+        let label = markers.format(&Annotation {
+            base: &markers.escape("A|B"),
+            siblings: Some(vec![&markers.escape(","), "NP"]),
+            parents: Some(vec![&markers.escape("x^<y>")]),
+        });
+        assert_eq!(label, "A\\|B|<\\,,NP>^<x\\^<y\\>>");
+        let annotation = markers.annotation(&label).unwrap();
+        assert_eq!(markers.unescape(annotation.base), "A|B");
+        assert_eq!(markers.unescape(annotation.siblings.unwrap()[0]), ",");
+        assert_eq!(markers.unescape(annotation.parents.unwrap()[0]), "x^<y>");
+
+        // not produced by binarise
+        assert_eq!(markers.annotation(","), None);
+        assert_eq!(markers.annotation("A|B"), None);
+        assert_eq!(markers.annotation("A|<B"), None);
+        assert_eq!(markers.annotation("A|<B>C"), None);
+        assert_eq!(markers.base("A|<B>C"), "A|<B>C");
+        assert_eq!(markers.base("A|<B>^<C>"), "A");
+        assert_eq!(markers.intermediate("A^<C>"), None);
+    }
+
+    #[test]
+    fn new_test() {
+        assert!(Markers::new("@", "#", ";", "!").is_ok());
+        assert!(Markers::new("", "#", ";", "!").is_err());
+        assert!(Markers::new("||", "#", ";", "!").is_err());
+        assert!(Markers::new("|", "|<", ";", "!").is_err());
+        assert!(Markers::new("(", "#", ";", "!").is_err());
+        let markers = Markers::new("@", "#", ";", "!").unwrap();
+        let label = markers.format(&Annotation {
+            base: "A",
+            siblings: Some(vec!["B", "C"]),
+            parents: None,
+        });
+        assert_eq!(label, "A@B;C!");
+        assert_eq!(markers.intermediate(&label).unwrap(), "A");
+    }
+}
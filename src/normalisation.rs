@@ -0,0 +1,206 @@
+use std::fmt::Display;
+
+use foldhash::HashMap;
+
+use crate::induce::parse_tree::escape;
+
+/// how an unknown word was mapped to a known word
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Normalisation {
+    Lowercase,
+    Unicode,
+    Digits,
+    /// the number of edits
+    EditDistance(usize),
+}
+
+impl Display for Normalisation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Normalisation::Lowercase => write!(f, "lowercase"),
+            Normalisation::Unicode => write!(f, "unicode"),
+            Normalisation::Digits => write!(f, "digits"),
+            Normalisation::EditDistance(distance) => write!(f, "edit distance {}", distance),
+        }
+    }
+}
+
+/// maps unknown words to spelling variants in the lexicon
+///
+/// the steps are tried in order and each one normalises the result of the previous one:
+/// lowercasing, folding of typographic and full-width characters, replacing digits with 0 and
+/// finally the nearest known word within the edit distance
+#[derive(Debug, Default)]
+pub struct Normaliser {
+    /// the known words for the normal forms after each step
+    steps: [HashMap<String, String>; 3],
+    /// the normal forms of the last step, sorted for the edit distance search
+    forms: Vec<String>,
+    max_distance: usize,
+}
+
+impl Normaliser {
+    /// `words` are the known words in their escaped form, the first one wins if several have the
+    /// same normal form unless another one already is in that form
+    pub fn new<'a>(words: impl IntoIterator<Item = &'a str>, max_distance: usize) -> Self {
+        let mut normaliser = Self {
+            max_distance,
+            ..Default::default()
+        };
+        for word in words {
+            for (form, step) in normal_forms(word).into_iter().zip(&mut normaliser.steps) {
+                if form == word || !step.contains_key(&form) {
+                    step.insert(form, word.to_string());
+                }
+            }
+        }
+        normaliser.forms = normaliser.steps[2].keys().cloned().collect();
+        normaliser.forms.sort();
+        normaliser
+    }
+
+    /// returns the known word for an unknown word and the step that found it
+    pub fn normalise(&self, word: &str) -> Option<(&str, Normalisation)> {
+        let forms = normal_forms(word);
+        let steps = [
+            Normalisation::Lowercase,
+            Normalisation::Unicode,
+            Normalisation::Digits,
+        ];
+        for ((form, known), step) in forms.iter().zip(&self.steps).zip(steps) {
+            if let Some(known) = known.get(form) {
+                return Some((known, step));
+            }
+        }
+
+        // short words would match too many others
+        let form: Vec<char> = forms[2].chars().collect();
+        let limit = self.max_distance.min(form.len() / 3);
+        let mut best: Option<(&str, usize)> = None;
+        for candidate in &self.forms {
+            let bound = best.map_or(limit, |(_, distance)| distance - 1);
+            if let Some(distance) = edit_distance(&form, candidate, bound) {
+                best = Some((candidate, distance));
+                if distance == 1 {
+                    break;
+                }
+            }
+        }
+        best.map(|(form, distance)| {
+            (
+                &self.steps[2][form][..],
+                Normalisation::EditDistance(distance),
+            )
+        })
+    }
+}
+
+/// the normal forms of the word after lowercasing, unicode folding and digit folding
+fn normal_forms(word: &str) -> [String; 3] {
+    let lowercase = word.to_lowercase();
+    let unicode = fold_unicode(&lowercase);
+    let digits = unicode
+        .chars()
+        .map(|c| if c.is_ascii_digit() { '0' } else { c })
+        .collect();
+    [lowercase, unicode, digits]
+}
+
+/// replaces the compatibility characters that are common in text with their plain forms, in the
+/// spirit of NFKC
+fn fold_unicode(word: &str) -> String {
+    let mut folded = String::with_capacity(word.len());
+    for c in word.chars() {
+        match c {
+            // full-width forms of ASCII, escaped like the tokens
+            '\u{ff01}'..='\u{ff5e}' => {
+                let ascii = char::from_u32(c as u32 - 0xfee0).unwrap().to_string();
+                folded.push_str(&escape(&ascii));
+            }
+            '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}' | '\u{2032}' => folded.push('\''),
+            '\u{201c}' | '\u{201d}' | '\u{201e}' | '\u{201f}' | '\u{2033}' => folded.push('"'),
+            '\u{2010}'..='\u{2015}' | '\u{2212}' => folded.push('-'),
+            '\u{2026}' => folded.push_str("..."),
+            '\u{fb00}' => folded.push_str("ff"),
+            '\u{fb01}' => folded.push_str("fi"),
+            '\u{fb02}' => folded.push_str("fl"),
+            '\u{fb03}' => folded.push_str("ffi"),
+            '\u{fb04}' => folded.push_str("ffl"),
+            // zero width characters and soft hyphens
+            '\u{00ad}' | '\u{200b}'..='\u{200d}' | '\u{feff}' => {}
+            _ => folded.push(c),
+        }
+    }
+    folded
+}
+
+/// the Levenshtein distance of the words if it is at most `bound`
+fn edit_distance(word: &[char], other: &str, bound: usize) -> Option<usize> {
+    let other: Vec<char> = other.chars().collect();
+    if bound == 0 || word.len().abs_diff(other.len()) > bound {
+        return None;
+    }
+    let mut previous: Vec<usize> = (0..=other.len()).collect();
+    for (i, a) in word.iter().enumerate() {
+        let mut current = vec![i + 1; other.len() + 1];
+        for (j, b) in other.iter().enumerate() {
+            current[j + 1] = (previous[j] + usize::from(a != b))
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        if current.iter().min().is_some_and(|e| *e > bound) {
+            return None;
+        }
+        previous = current;
+    }
+    Some(previous[other.len()]).filter(|e| *e <= bound)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalise_test() {
+        let words = ["The", "the", "colour", "1984", "fish", "Hello", "don't"];
+        let normaliser = Normaliser::new(words, 2);
+        assert_eq!(
+            normaliser.normalise("THE"),
+            Some(("the", Normalisation::Lowercase))
+        );
+        assert_eq!(
+            normaliser.normalise("ＦＩＳＨ"),
+            Some(("fish", Normalisation::Unicode))
+        );
+        assert_eq!(
+            normaliser.normalise("don\u{2019}t"),
+            Some(("don't", Normalisation::Unicode))
+        );
+        assert_eq!(
+            normaliser.normalise("2001"),
+            Some(("1984", Normalisation::Digits))
+        );
+        assert_eq!(
+            normaliser.normalise("color"),
+            Some(("colour", Normalisation::EditDistance(1)))
+        );
+        assert_eq!(
+            normaliser.normalise("Hello!!"),
+            Some(("Hello", Normalisation::EditDistance(2)))
+        );
+        assert_eq!(
+            Normaliser::new(["\\("], 2).normalise("（"),
+            Some(("\\(", Normalisation::Unicode))
+        );
+        assert_eq!(normaliser.normalise("cat"), None);
+        assert_eq!(Normaliser::new(words, 0).normalise("color"), None);
+    }
+
+    #[test]
+    fn edit_distance_test() {
+        let word: Vec<char> = "kitten".chars().collect();
+        assert_eq!(edit_distance(&word, "sitting", 3), Some(3));
+        assert_eq!(edit_distance(&word, "sitting", 2), None);
+        assert_eq!(edit_distance(&word, "kitten", 1), Some(0));
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt::{Display, Write};
+
+use clap::{Args, ValueEnum};
+
+use crate::{
+    induce::parse_tree::{ParseTree, unescape},
+    json::Json,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// one s-expression per line
+    #[default]
+    Ptb,
+    /// indented s-expressions, one constituent above the preterminals per line
+    Pretty,
+    /// one JSON object per line with label, span and children of each node
+    Json,
+    /// one GraphViz digraph per tree
+    Dot,
+    /// one `label start end` line per constituent, trees are separated by a blank line
+    Spans,
+}
+
+// the output option shared by all subcommands that write trees
+#[derive(Args, Clone, Debug)]
+pub struct Output {
+    /// how the trees are written
+    #[arg(long, value_enum, default_value_t = OutputFormat::Ptb)]
+    pub output_format: OutputFormat,
+}
+
+/// returns the tree in the format, only the span list ends with a newline so that the trees
+/// are separated by a blank line
+pub fn format_tree<T: AsRef<str> + Display>(tree: &ParseTree<T>, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Ptb => tree.to_string(),
+        OutputFormat::Pretty => {
+            let mut output = String::new();
+            pretty(tree, 0, &mut output);
+            output
+        }
+        OutputFormat::Json => to_json(tree, &mut 0).to_string(),
+        OutputFormat::Dot => {
+            let mut output = String::from("digraph tree {\n");
+            dot(tree, &mut 0, &mut output);
+            output.push('}');
+            output
+        }
+        OutputFormat::Spans => {
+            let mut lines = Vec::new();
+            spans(tree, &mut 0, &mut lines);
+            lines.join("\n") + "\n"
+        }
+    }
+}
+
+/// writes the tree on one line if it only spans preterminals, otherwise each child is indented
+/// on its own line
+fn pretty<T: AsRef<str> + Display>(tree: &ParseTree<T>, indent: usize, output: &mut String) {
+    let flat = tree
+        .children
+        .iter()
+        .all(|child| child.children.iter().all(ParseTree::is_leaf));
+    if flat {
+        output.push_str(&tree.to_string());
+        return;
+    }
+    write!(output, "({}", tree.root).unwrap();
+    for child in &tree.children {
+        write!(output, "\n{:width$}", "", width = indent + 2).unwrap();
+        pretty(child, indent + 2, output);
+    }
+    output.push(')');
+}
+
+/// the span of a node is `[start, end)` over the words, `position` is the first word of `tree`
+fn to_json<T: AsRef<str>>(tree: &ParseTree<T>, position: &mut usize) -> Json {
+    let start = *position;
+    let children: Vec<Json> = tree
+        .children
+        .iter()
+        .map(|child| to_json(child, position))
+        .collect();
+    if tree.is_leaf() {
+        *position += 1;
+    }
+    Json::object([
+        ("label", unescape(tree.root.as_ref()).into_owned().into()),
+        ("span", Json::Array(vec![start.into(), (*position).into()])),
+        ("children", Json::Array(children)),
+    ])
+}
+
+/// writes the nodes and edges of the tree and returns the id of its root
+fn dot<T: AsRef<str>>(tree: &ParseTree<T>, next_id: &mut usize, output: &mut String) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+    let label = unescape(tree.root.as_ref())
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"");
+    if tree.is_leaf() {
+        writeln!(output, "  n{} [label=\"{}\", shape=plaintext];", id, label).unwrap();
+    } else {
+        writeln!(output, "  n{} [label=\"{}\"];", id, label).unwrap();
+    }
+    for child in &tree.children {
+        let child_id = dot(child, next_id, output);
+        writeln!(output, "  n{} -> n{};", id, child_id).unwrap();
+    }
+    id
+}
+
+fn spans<T: AsRef<str>>(tree: &ParseTree<T>, position: &mut usize, lines: &mut Vec<String>) {
+    if tree.is_leaf() {
+        *position += 1;
+        return;
+    }
+    let start = *position;
+    let index = lines.len();
+    lines.push(String::new());
+    for child in &tree.children {
+        spans(child, position, lines);
+    }
+    lines[index] = format!("{} {} {}", unescape(tree.root.as_ref()), start, position);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::induce::parse_tree::element;
+
+    const TREE: &str = "(ROOT (S (NP (DT the) (NN dog)) (VP (VBD saw) (NP (NN New\\ York)))))";
+
+    #[test]
+    fn formats_test() {
+        let (_, tree) = element(TREE).unwrap();
+        assert_eq!(format_tree(&tree, OutputFormat::Ptb), TREE);
+        assert_eq!(
+            format_tree(&tree, OutputFormat::Pretty),
+            "(ROOT\n  (S\n    (NP (DT the) (NN dog))\n    (VP\n      (VBD saw)\n      (NP (NN New\\ York)))))"
+        );
+        assert_eq!(
+            format_tree(&tree, OutputFormat::Spans),
+            "ROOT 0 4\nS 0 4\nNP 0 2\nDT 0 1\nNN 1 2\nVP 2 4\nVBD 2 3\nNP 3 4\nNN 3 4\n"
+        );
+        let json = format_tree(&tree, OutputFormat::Json);
+        assert!(json.starts_with(r#"{"label":"ROOT","span":[0,4],"children":[{"label":"S""#));
+        assert!(json.contains(r#"{"label":"New York","span":[3,4],"children":[]}"#));
+        let dot = format_tree(&tree, OutputFormat::Dot);
+        assert!(dot.starts_with("digraph tree {\n  n0 [label=\"ROOT\"];\n"));
+        assert!(dot.contains("n12 [label=\"New York\", shape=plaintext];"));
+        assert!(dot.ends_with("n0 -> n1;\n}"));
+    }
+
+    #[test]
+    fn escaped_label_test() {
+        // all formats but the bracketed ones write the label as it is meant
+        let (_, tree) = element("(S\\|X (A a))").unwrap();
+        assert_eq!(format_tree(&tree, OutputFormat::Spans), "S|X 0 1\nA 0 1\n");
+        assert!(format_tree(&tree, OutputFormat::Json).starts_with(r#"{"label":"S|X""#));
+        assert!(format_tree(&tree, OutputFormat::Dot).contains("n0 [label=\"S|X\"];"));
+    }
+}
@@ -1,4 +1,6 @@
+pub mod backoff;
 pub mod consequence;
+pub mod embeddings;
 mod max_queue;
 pub mod rule;
 pub mod string_lookup;
@@ -7,12 +9,26 @@ pub mod weight_map;
 use std::{
     fs::File,
     io::{self, BufRead, BufReader},
-    path::Path,
+    path::{Path, PathBuf},
     process::exit,
 };
 
-use crate::{astar::ViterbiScore, parse::rule::Rule, smoothing::smooth_word};
+use crate::{
+    astar::ViterbiScore,
+    head::strip_annotation,
+    induce::{
+        parse_tree::{ParseTree, restore_brackets, sentence_tokens},
+        suffix_model::SuffixModel,
+    },
+    normalisation::{Normalisation, Normaliser},
+    output::{OutputFormat, format_tree},
+    parse::rule::Rule,
+    smoothing::SignatureScheme,
+    split_merge::project,
+};
+use backoff::{add_backoff_rules, remove_backoff_nodes};
 use consequence::Consequence;
+use embeddings::Embeddings;
 use foldhash::HashSet;
 use foldhash::{HashMap, HashMapExt};
 use max_queue::MaxQueue;
@@ -20,16 +36,36 @@ use rule::Rhs;
 use string_lookup::StringLookup;
 use weight_map::{Item, WeightMap};
 
+/// how `parse` handles the words that are not in the lexicon
+pub struct UnknownWords {
+    pub unking: bool,
+    pub smoothing: Option<SignatureScheme>,
+    /// the suffix model written by induce
+    pub suffix_model: Option<PathBuf>,
+    /// word vectors in the text format of word2vec or GloVe
+    pub embeddings: Option<PathBuf>,
+    /// the number of similar known words of an unknown word with a vector
+    pub neighbours: usize,
+    /// the largest edit distance of the normaliser, without it there is no normalisation
+    pub normalisation: Option<usize>,
+    /// report how unknown words are mapped
+    pub verbose: bool,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn parse(
     rules: &Path,
     lexicon: &Path,
     paradigma: &Option<String>,
     initial_nonterminal: &str,
-    unking: &bool,
-    smoothing: &bool,
+    unknown: &UnknownWords,
     threshold_beam: &Option<u64>,
     rank_beam: &Option<u64>,
     astar: &Option<std::path::PathBuf>,
+    lexicalised: Option<f64>,
+    latent: &bool,
+    restore: &bool,
+    output_format: OutputFormat,
 ) {
     match paradigma {
         Some(paradigma) if paradigma == &"cyk".to_string() => exit(22),
@@ -56,13 +92,54 @@ pub fn parse(
         lexicon,
         false,
     );
+    let preterminals: HashSet<Item> = all_rules
+        .iter()
+        .filter(|(_, body)| {
+            body.keys()
+                .any(|rhs| matches!(rhs, Rhs::Unary(Item::Terminal(_))))
+        })
+        .map(|(lhs, _)| *lhs)
+        .collect();
     let initial_nonterminal = Item::NonTerminal(
         string_lookup
             .get(initial_nonterminal)
             .expect("initial nonterminal is not in the rules") as u32,
     );
     rule_lookup.entry(initial_nonterminal).or_default();
+    if let Some(penalty) = lexicalised {
+        add_backoff_rules(
+            &mut string_lookup,
+            &mut rule_lookup,
+            &mut all_rules,
+            penalty,
+        );
+    }
 
+    let is_known = |word: &str| {
+        string_lookup
+            .get(word)
+            .is_some_and(|e| rule_lookup.contains_key(&Item::Terminal(e as u32)))
+    };
+    let normaliser = unknown.normalisation.map(|max_distance| {
+        let words = (0..string_lookup.len()).filter_map(|e| string_lookup.get_string(e));
+        Normaliser::new(words.map(|e| &e[..]).filter(|e| is_known(e)), max_distance)
+    });
+    let suffix_model = unknown.suffix_model.as_deref().map(SuffixModel::from_file);
+    if let Some(suffix_model) = &suffix_model {
+        let is_preterminal = |tag: &str| {
+            string_lookup
+                .get(tag)
+                .is_some_and(|e| preterminals.contains(&Item::NonTerminal(e as u32)))
+        };
+        if !suffix_model.tags().any(is_preterminal) {
+            eprintln!("none of the tags of the suffix model are tags of the grammar");
+        }
+    }
+    let embeddings = unknown.embeddings.as_deref().map(|path| {
+        let mut embeddings = Embeddings::from_file(path);
+        embeddings.set_known(is_known);
+        embeddings
+    });
     let scores = astar.as_ref().map(|astar| {
         ViterbiScore::new_from_file(astar, &string_lookup)
             .expect("Could not read from .outside file!")
@@ -72,7 +149,54 @@ pub fn parse(
             eprintln!("error reading line {}", line_number + 1);
             exit(1);
         };
-        if let Some(line_items) = transform_sentence(&line, &string_lookup, unking, smoothing) {
+        // each unknown word is normalised once, the words with a spelling variant in the
+        // lexicon are not given to the embeddings and the suffix model
+        let tokens = sentence_tokens(&line);
+        let variants: Vec<Option<(&str, Normalisation)>> = tokens
+            .iter()
+            .map(|word| match &normaliser {
+                Some(normaliser) if string_lookup.get(word).is_none() => normaliser.normalise(word),
+                _ => None,
+            })
+            .collect();
+        let unknown_words: Vec<String> = tokens
+            .iter()
+            .zip(&variants)
+            .filter(|(word, variant)| variant.is_none() && string_lookup.get(word).is_none())
+            .map(|(word, _)| word.clone())
+            .collect();
+        // the rules of the unknown words only hold for this sentence
+        let known_strings = string_lookup.len();
+        if let Some(embeddings) = &embeddings {
+            add_similar_words(
+                &unknown_words,
+                embeddings,
+                unknown.neighbours,
+                unknown.verbose,
+                &mut string_lookup,
+                &mut rule_lookup,
+                &mut all_rules,
+            );
+        }
+        if let Some(suffix_model) = &suffix_model {
+            add_unknown_words(
+                &unknown_words,
+                suffix_model,
+                &preterminals,
+                unknown.verbose,
+                &mut string_lookup,
+                &mut rule_lookup,
+                &mut all_rules,
+            );
+        }
+        if let Some(line_items) = transform_sentence(
+            &line,
+            &string_lookup,
+            &unknown.unking,
+            unknown.smoothing.as_ref(),
+            &variants,
+            unknown.verbose,
+        ) {
             let rule_weights = deduce(
                 &line_items,
                 &rule_lookup,
@@ -81,22 +205,163 @@ pub fn parse(
                 string_lookup.len(),
             );
             if rule_weights.get_with_index(initial_nonterminal, 0, line_items.len() as u32) == 0.0 {
-                println!("(NOPARSE {})", line)
+                println!("{}", format_tree(&noparse(&line), output_format))
             } else {
-                let tree = rule_weights.convert_to_parse_tree(
+                let mut tree = rule_weights.convert_to_parse_tree(
                     initial_nonterminal,
                     0,
                     line_items.len() as u32,
                     &string_lookup,
                     &all_rules,
-                    &mut line.split_whitespace().collect(),
+                    &mut tokens.iter().map(|e| &e[..]).collect(),
                 );
-                println!("{tree}")
+                if *restore {
+                    restore_brackets(&mut tree);
+                }
+                if lexicalised.is_some() {
+                    remove_backoff_nodes(&mut tree);
+                }
+                if lexicalised.is_some() || *latent {
+                    tree.change_nodes(&mut |node| {
+                        if node.is_leaf() {
+                            return;
+                        }
+                        if *latent {
+                            node.root = project(&node.root).into_owned();
+                        }
+                        if lexicalised.is_some() {
+                            node.root = strip_annotation(&node.root).to_string();
+                        }
+                    });
+                }
+                println!("{}", format_tree(&tree, output_format))
             }
         } else {
             eprintln!("Not all Tokens are in the grammar");
-            println!("(NOPARSE {})", line);
+            println!("{}", format_tree(&noparse(&line), output_format));
+
+        }
+        remove_added_words(
+            known_strings,
+            &mut string_lookup,
+            &mut rule_lookup,
+            &mut all_rules,
+        );
+    }
+}
+
+/// the tree that is written for a sentence without a parse: `(NOPARSE w1 w2 …)`
+fn noparse(line: &str) -> ParseTree<String> {
+    ParseTree {
+        root: "NOPARSE".to_string(),
+        children: sentence_tokens(line).into_iter().map(ParseTree::new).collect(),
+    }
+}
+
+/// adds the lexical rules of the suffix model for the words that are not in the lexicon, so
+/// that `transform_sentence` finds them instead of substituting them
+fn add_unknown_words(
+    words: &[String],
+    suffix_model: &SuffixModel,
+    preterminals: &HashSet<Item>,
+    verbose: bool,
+    string_map: &mut StringLookup,
+    rhs_grammar: &mut HashMap<Item, HashSet<Rule<Item>>>,
+    all_rules: &mut HashMap<Item, HashMap<Rhs<Item>, f64>>,
+) {
+    for word in words {
+        if string_map.get(word).is_some() {
+            continue;
+        }
+        // tags of the model that have no lexical rules in the grammar cannot be used
+        let weights: Vec<(&str, f64)> = suffix_model
+            .tag_weights(word)
+            .into_iter()
+            .filter(|(tag, _)| {
+                string_map
+                    .get(tag)
+                    .is_some_and(|e| preterminals.contains(&Item::NonTerminal(e as u32)))
+            })
+            .collect();
+        if verbose && !weights.is_empty() {
+            let tags: Vec<&str> = weights.iter().map(|(tag, _)| *tag).collect();
+            eprintln!("'{}' -> '{}' (suffix model)", word, tags.join("', '"));
+        }
+        for (tag, weight) in weights {
+            let line = format!("{} {} {}", tag, word, weight);
+            insert_into_lookup(string_map, false, rhs_grammar, all_rules, line);
+        }
+    }
+}
+
+/// removes the words that were added to `string_map` after the first `known_strings` together
+/// with their lexical rules
+fn remove_added_words(
+    known_strings: usize,
+    string_map: &mut StringLookup,
+    rhs_grammar: &mut HashMap<Item, HashSet<Rule<Item>>>,
+    all_rules: &mut HashMap<Item, HashMap<Rhs<Item>, f64>>,
+) {
+    for word in known_strings..string_map.len() {
+        let Some(rules) = rhs_grammar.remove(&Item::Terminal(word as u32)) else {
+            continue;
+        };
+        for rule in rules {
+            if let Some(body) = all_rules.get_mut(&rule.lhs) {
+                body.remove(&rule.rhs);
+            }
+        }
+    }
+    string_map.truncate(known_strings);
+}
 
+/// adds lexical rules for the words that are not in the lexicon but have a vector,
+/// the weight of `t -> w` is the mean weight of `t -> n` over the `neighbours` most similar known
+/// words n, weighted by their similarity to w
+fn add_similar_words(
+    words: &[String],
+    embeddings: &Embeddings,
+    neighbours: usize,
+    verbose: bool,
+    string_map: &mut StringLookup,
+    rhs_grammar: &mut HashMap<Item, HashSet<Rule<Item>>>,
+    all_rules: &mut HashMap<Item, HashMap<Rhs<Item>, f64>>,
+) {
+    for word in words {
+        if string_map.get(word).is_some() {
+            continue;
+        }
+        let nearest = embeddings.nearest(word, neighbours);
+        if verbose && !nearest.is_empty() {
+            let words: Vec<&str> = nearest
+                .iter()
+                .map(|(neighbour, _)| &neighbour[..])
+                .collect();
+            eprintln!("'{}' -> '{}' (embeddings)", word, words.join("', '"));
+        }
+        let total: f64 = nearest
+            .iter()
+            .map(|(_, similarity)| *similarity as f64)
+            .sum();
+        let mut weights: HashMap<Item, f64> = HashMap::new();
+        for (neighbour, similarity) in nearest {
+            let Some(id) = string_map.get(neighbour) else {
+                continue;
+            };
+            let rules = rhs_grammar.get(&Item::Terminal(id as u32));
+            for rule in rules.into_iter().flatten() {
+                *weights.entry(rule.lhs).or_default() += similarity as f64 / total * rule.weight;
+            }
+        }
+        let mut weights: Vec<(Item, f64)> = weights.into_iter().collect();
+        weights.sort_by_key(|(tag, _)| *tag);
+        for (tag, weight) in weights {
+            let Item::NonTerminal(tag) = tag else {
+                continue;
+            };
+            let tag = string_map.get_string(tag as usize).unwrap().clone();
+            let line = format!("{} {} {}", tag, word, weight);
+            insert_into_lookup(string_map, false, rhs_grammar, all_rules, line);
         }
     }
 }
@@ -122,7 +387,7 @@ pub fn parse_rules(
     }
 }
 
-fn insert_into_lookup(
+pub fn insert_into_lookup(
     string_map: &mut StringLookup,
     is_rule: bool,
     rhs_grammar: &mut HashMap<Item, HashSet<Rule<Item>>>,
@@ -178,35 +443,59 @@ fn insert_rule(
     set.insert(Rule { lhs, rhs, weight });
 }
 
+/// maps the words of the line to terminals, unknown words are mapped to their known spelling
+/// variant in `variants` at their position first and then replaced with UNK or their signature
 pub fn transform_sentence(
     line: &str,
     lexicon: &StringLookup,
     unking: &bool,
-    smoothing: &bool,
+    smoothing: Option<&SignatureScheme>,
+    variants: &[Option<(&str, Normalisation)>],
+    verbose: bool,
 ) -> Option<Vec<Item>> {
-    line.split_whitespace()
-        .map(|word| {
-            let word_id = match lexicon.get(word) {
-                Some(u) => u,
-                None => {
-                    if *unking {
-                        lexicon
-                            .get("UNK")
-                            .expect("UNK is not in the lexicon. Did you use an unked input?")
-                    } else if *smoothing {
-                        lexicon.get(&smooth_word(word, true)).unwrap_or_else(|| {
-                            lexicon.get("UNK-S").unwrap_or_else(|| {
-                            eprintln!("{} is not in the lexicon. Use smoothed input or the input data is not sufficient", word);
-                            exit(1);
-                            })
-                        })
-                    } else {
-                        eprintln!("'{}' is not in the lexicon. Maybe use unking", word);
-                        return None;
-                    }
+    sentence_tokens(line)
+        .iter()
+        .enumerate()
+        .map(|(position, word)| {
+            if let Some(u) = lexicon.get(word) {
+                return Some(Item::Terminal(u as u32));
+            }
+            let variant = variants
+                .get(position)
+                .copied()
+                .flatten()
+                .and_then(|(known, step)| Some((lexicon.get(known)?, known, step)));
+            let word_id = if let Some((u, known, step)) = variant {
+                if verbose {
+                    eprintln!("'{}' -> '{}' ({})", word, known, step);
+                }
+                u
+            } else if *unking {
+                if verbose {
+                    eprintln!("'{}' -> 'UNK'", word);
                 }
-            } as u32;
-            Some(Item::Terminal(word_id))
+                lexicon
+                    .get("UNK")
+                    .expect("UNK is not in the lexicon. Did you use an unked input?")
+            } else if let Some(scheme) = smoothing {
+                // the coarser signatures are tried if the finer ones were not seen
+                let (u, signature) = scheme
+                    .signatures(word, position == 0)
+                    .into_iter()
+                    .find_map(|signature| Some((lexicon.get(&signature)?, signature)))
+                    .unwrap_or_else(|| {
+                        eprintln!("{} is not in the lexicon. Use smoothed input or the input data is not sufficient", word);
+                        exit(1);
+                    });
+                if verbose {
+                    eprintln!("'{}' -> '{}' (signature)", word, signature);
+                }
+                u
+            } else {
+                eprintln!("'{}' is not in the lexicon. Maybe use unking", word);
+                return None;
+            };
+            Some(Item::Terminal(word_id as u32))
         })
         .collect()
 }
@@ -399,6 +688,117 @@ mod test {
         );
     }
 
+    #[test]
+    fn transform_sentence_signature_test() {
+        let string_map = StringLookup::from_iter(["UNK-SC", "UNK-C", "test"].map(String::from));
+        let scheme = SignatureScheme::default();
+        // only the first token is sentence-initial, as in smooth
+        assert_eq!(
+            transform_sentence(
+                "Try test Try",
+                &string_map,
+                &false,
+                Some(&scheme),
+                &[],
+                false
+            )
+            .unwrap(),
+            vec![Item::Terminal(0), Item::Terminal(2), Item::Terminal(1)]
+        );
+        // the spelling variants are found before the signatures
+        let variants = [None, None, Some(("test", Normalisation::Lowercase))];
+        assert_eq!(
+            transform_sentence(
+                "Try test TEST",
+                &string_map,
+                &false,
+                Some(&scheme),
+                &variants,
+                false
+            )
+            .unwrap(),
+            vec![Item::Terminal(0), Item::Terminal(2), Item::Terminal(2)]
+        );
+    }
+
+    #[test]
+    fn add_similar_words_test() {
+        let mut string_map = StringLookup::default();
+        let mut grammar = HashMap::new();
+        let mut all_rules = HashMap::new();
+        for line in ["NN dog 0.5", "NN cat 0.3", "VB cat 0.2", "VB run 0.8"] {
+            insert_into_lookup(
+                &mut string_map,
+                false,
+                &mut grammar,
+                &mut all_rules,
+                line.to_string(),
+            );
+        }
+        let vectors = "dog 1 0\ncat 1 1\nrun 0 1\npuppy 1 0.1\n";
+        let mut embeddings = Embeddings::read(vectors.as_bytes()).unwrap();
+        embeddings.set_known(|word| string_map.get(word).is_some());
+        let known_strings = string_map.len();
+        let known_rules = all_rules.clone();
+        add_similar_words(
+            &["the".to_string(), "puppy".to_string()],
+            &embeddings,
+            2,
+            false,
+            &mut string_map,
+            &mut grammar,
+            &mut all_rules,
+        );
+        assert!(string_map.get("the").is_none());
+        let puppy = Item::Terminal(string_map.get("puppy").unwrap() as u32);
+        let weight = |tag| {
+            let tag = Item::NonTerminal(string_map.get(tag).unwrap() as u32);
+            all_rules[&tag][&Rhs::Unary(puppy)]
+        };
+        assert!(weight("NN") > 0.3 && weight("NN") < 0.5);
+        assert!(weight("VB") > 0.0 && weight("VB") < 0.1);
+        assert_eq!(grammar[&puppy].len(), 2);
+
+        // after the sentence the word is unknown again
+        remove_added_words(known_strings, &mut string_map, &mut grammar, &mut all_rules);
+        assert!(string_map.get("puppy").is_none());
+        assert_eq!(string_map.len(), known_strings);
+        assert!(!grammar.contains_key(&puppy));
+        assert_eq!(all_rules, known_rules);
+    }
+
+    #[test]
+    fn add_unknown_words_test() {
+        let mut string_map = StringLookup::default();
+        let mut grammar = HashMap::new();
+        let mut all_rules = HashMap::new();
+        for (line, is_rule) in [("S -> VB NN 1", true), ("NN dog 1", false)] {
+            insert_into_lookup(
+                &mut string_map,
+                is_rule,
+                &mut grammar,
+                &mut all_rules,
+                line.to_string(),
+            );
+        }
+        let nn = Item::NonTerminal(string_map.get("NN").unwrap() as u32);
+        let preterminals = HashSet::from_iter([nn]);
+        // VB is a non-terminal of the grammar but has no lexical rules, dog is a word
+        let suffix_model = SuffixModel::read("NN 2\nVB 1\ndog 1\nJJ 1\n".as_bytes()).unwrap();
+        add_unknown_words(
+            &["cat".to_string()],
+            &suffix_model,
+            &preterminals,
+            false,
+            &mut string_map,
+            &mut grammar,
+            &mut all_rules,
+        );
+        let cat = Item::Terminal(string_map.get("cat").unwrap() as u32);
+        let tags: Vec<Item> = grammar[&cat].iter().map(|rule| rule.lhs).collect();
+        assert_eq!(tags, vec![nn]);
+    }
+
     #[test]
     fn into_map_from_rules_test() {
         let mut string_map = StringLookup::default();
@@ -559,7 +959,7 @@ mod test {
         let initial = Item::NonTerminal(string_map.get("ROOT").unwrap() as u32);
         grammar.entry(initial).or_default();
 
-        let line = transform_sentence("R S T", &string_map, &false, &false).unwrap();
+        let line = transform_sentence("R S T", &string_map, &false, None, &[], false).unwrap();
         let mut desired_weight_map = WeightMap::with_capacity(string_map.len(), line.len());
         // R: 0
         // W1: 1
@@ -0,0 +1,215 @@
+use std::borrow::Cow;
+
+use foldhash::{HashMap, HashMapExt, HashSet};
+
+use super::{Item, Rhs, Rule, StringLookup, insert_into_lookup};
+use crate::{
+    head::{split_annotation, strip_annotation},
+    induce::parse_tree::ParseTree,
+};
+
+/// adds back-off rules to a lexicalised grammar, so that sentences whose head words or
+/// lexicalised labels were not seen together in training can still be parsed
+///
+/// every rule is added again with the labels `X[T/w]` reduced to `X[T]` and with all labels
+/// reduced to `X`, the weight of a reduced rule is the mean weight of the rules it stands for.
+/// the unary rules `X[T] -> X[T/w]` and `X -> X[T]` with the weight `penalty` connect the levels,
+/// so the most lexicalised analysis is preferred if there is one
+pub fn add_backoff_rules(
+    string_map: &mut StringLookup,
+    rhs_grammar: &mut HashMap<Item, HashSet<Rule<Item>>>,
+    all_rules: &mut HashMap<Item, HashMap<Rhs<Item>, f64>>,
+    penalty: f64,
+) {
+    let label = |item: &Item| string_map.get_string(usize::from(*item)).unwrap().clone();
+    let mut rules: Vec<(String, Vec<String>, f64)> = Vec::new();
+    let mut labels: HashSet<String> = HashSet::default();
+    for (lhs, body) in all_rules.iter() {
+        labels.insert(label(lhs));
+        for (rhs, weight) in body {
+            let children = match rhs {
+                Rhs::Unary(Item::Terminal(_)) => continue,
+                Rhs::Unary(child) => vec![label(child)],
+                Rhs::Binary(left, right) => vec![label(left), label(right)],
+            };
+            labels.extend(children.iter().cloned());
+            rules.push((label(lhs), children, *weight));
+        }
+    }
+
+    let mut backoff_rules: Vec<(String, Vec<String>, f64)> = Vec::new();
+    for reduce in [reduce_to_tag, reduce_to_base] {
+        let mut weights: HashMap<(String, Vec<String>), f64> = HashMap::new();
+        let mut members: HashMap<String, HashSet<&str>> = HashMap::new();
+        for (lhs, children, weight) in &rules {
+            let reduced_lhs = reduce(lhs).to_string();
+            let reduced: Vec<String> = children.iter().map(|e| reduce(e).to_string()).collect();
+            if &reduced_lhs == lhs && &reduced == children {
+                continue;
+            }
+            members.entry(reduced_lhs.clone()).or_default().insert(lhs);
+            *weights.entry((reduced_lhs, reduced)).or_default() += weight;
+        }
+        let mut weights: Vec<_> = weights.into_iter().collect();
+        weights.sort_by(|a, b| a.0.cmp(&b.0));
+        for ((lhs, children), weight) in weights {
+            let weight = weight / members[&lhs].len() as f64;
+            backoff_rules.push((lhs, children, weight));
+        }
+    }
+    let mut labels: Vec<String> = labels.into_iter().collect();
+    labels.sort();
+    for label in labels {
+        let tag = reduce_to_tag(&label);
+        let base = reduce_to_base(&label);
+        if base != tag {
+            backoff_rules.push((base.to_string(), vec![tag.to_string()], penalty));
+        }
+        if tag != label {
+            backoff_rules.push((tag.to_string(), vec![label.clone()], penalty));
+        }
+    }
+    let mut added: HashSet<(String, Vec<String>)> = HashSet::default();
+    for (lhs, children, weight) in backoff_rules {
+        // the rules of the grammar are kept, a reduced rule is only added once
+        if is_rule(string_map, all_rules, &lhs, &children)
+            || !added.insert((lhs.clone(), children.clone()))
+        {
+            continue;
+        }
+        let line = format!("{} -> {} {}", lhs, children.join(" "), weight);
+        insert_into_lookup(string_map, true, rhs_grammar, all_rules, line);
+    }
+}
+
+/// whether the rule `lhs -> children` is in the grammar
+fn is_rule(
+    string_map: &StringLookup,
+    all_rules: &HashMap<Item, HashMap<Rhs<Item>, f64>>,
+    lhs: &str,
+    children: &[String],
+) -> bool {
+    let item = |label: &str| string_map.get(label).map(|e| Item::NonTerminal(e as u32));
+    let rhs = match children {
+        [child] => item(child).map(Rhs::Unary),
+        [left, right] => item(left).zip(item(right)).map(|(l, r)| Rhs::Binary(l, r)),
+        _ => None,
+    };
+    match (item(lhs), rhs) {
+        (Some(lhs), Some(rhs)) => all_rules.get(&lhs).is_some_and(|e| e.contains_key(&rhs)),
+        _ => false,
+    }
+}
+
+/// `X[T/w]` becomes `X[T]`, other labels are kept
+fn reduce_to_tag(label: &str) -> Cow<'_, str> {
+    match split_annotation(label) {
+        Some((base, tag, Some(_))) => Cow::Owned(format!("{base}[{tag}]")),
+        _ => Cow::Borrowed(label),
+    }
+}
+
+fn reduce_to_base(label: &str) -> Cow<'_, str> {
+    Cow::Borrowed(strip_annotation(label))
+}
+
+/// removes the nodes of the back-off rules `X[T] -> X[T/w]` and `X -> X[T]` from a parse, the
+/// more specific label is kept
+pub fn remove_backoff_nodes(tree: &mut ParseTree<String>) {
+    while let [child] = &mut tree.children[..] {
+        if child.is_leaf() || !is_backoff(&tree.root, &child.root) {
+            break;
+        }
+        let child = tree.children.pop().unwrap();
+        *tree = child;
+    }
+    for child in &mut tree.children {
+        remove_backoff_nodes(child);
+    }
+}
+
+fn is_backoff(general: &str, specific: &str) -> bool {
+    general != specific
+        && (reduce_to_tag(specific) == general
+            || (general == reduce_to_base(specific) && reduce_to_tag(specific) == specific))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::parse::{deduce, transform_sentence};
+
+    #[test]
+    fn backoff_test() {
+        let mut string_map = StringLookup::default();
+        let mut grammar = HashMap::new();
+        let mut all_rules = HashMap::new();
+        let rules = [
+            "ROOT -> S[VBZ/barks] 1",
+            "S[VBZ/barks] -> NP[NN/dog] VP[VBZ/barks] 1",
+            "S[VBZ/sleeps] -> NP[NN/cat] VP[VBZ/sleeps] 1",
+            "VP[VBZ/barks] -> VBZ[VBZ/barks] 1",
+            "VP[VBZ/sleeps] -> VBZ[VBZ/sleeps] 1",
+            "NP[NN/dog] -> NN[NN/dog] 1",
+            "NP[NN/cat] -> NN[NN/cat] 1",
+        ];
+        for line in rules {
+            insert_into_lookup(
+                &mut string_map,
+                true,
+                &mut grammar,
+                &mut all_rules,
+                line.to_string(),
+            );
+        }
+        for line in [
+            "NN[NN/dog] dog 1",
+            "NN[NN/cat] cat 1",
+            "VBZ[VBZ/barks] barks 1",
+            "VBZ[VBZ/sleeps] sleeps 1",
+        ] {
+            insert_into_lookup(
+                &mut string_map,
+                false,
+                &mut grammar,
+                &mut all_rules,
+                line.to_string(),
+            );
+        }
+        let initial = Item::NonTerminal(string_map.get("ROOT").unwrap() as u32);
+        add_backoff_rules(&mut string_map, &mut grammar, &mut all_rules, 0.1);
+        let item = |label| Item::NonTerminal(string_map.get(label).unwrap() as u32);
+        let s_vbz = item("S[VBZ]");
+        assert_eq!(
+            all_rules[&s_vbz][&Rhs::Binary(item("NP[NN]"), item("VP[VBZ]"))],
+            1.0
+        );
+        assert_eq!(all_rules[&initial][&Rhs::Unary(item("S[VBZ]"))], 1.0);
+        assert_eq!(all_rules[&item("S")][&Rhs::Unary(s_vbz)], 0.1);
+
+        // cat and barks were not seen together
+        let line = transform_sentence("cat barks", &string_map, &false, None, &[], false).unwrap();
+        let weights = deduce(&line, &grammar, None, initial, string_map.len());
+        let weight = weights.get_with_index(initial, 0, 2);
+        assert!((weight - 0.01).abs() < 1e-9);
+        let mut tree = weights.convert_to_parse_tree(
+            initial,
+            0,
+            2,
+            &string_map,
+            &all_rules,
+            &mut VecDeque::from(["cat", "barks"]),
+        );
+        remove_backoff_nodes(&mut tree);
+        assert_eq!(tree.children[0].root, "S[VBZ]");
+        tree.change_nodes(&mut |node| {
+            node.root = strip_annotation(&node.root).to_string();
+        });
+        assert_eq!(
+            tree.to_string(),
+            "(ROOT (S (NP (NN cat)) (VP (VBZ barks))))"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::{io::BufRead, path::Path};
+
+use foldhash::HashMap;
+
+use crate::induce::parse_tree::{escape, read_or_exit};
+
+/// word vectors in the text format of word2vec or GloVe, normalised to unit length
+#[derive(Debug, Default)]
+pub struct Embeddings {
+    /// the words are in their escaped form
+    vectors: HashMap<String, Vec<f32>>,
+    /// the words that unknown words can be mapped to
+    known: Vec<String>,
+}
+
+impl Embeddings {
+    /// reads `word v1 v2 …` lines, the `count dimension` header of word2vec is skipped
+    pub fn read(reader: impl BufRead) -> Result<Self, String> {
+        let mut vectors = HashMap::default();
+        let mut dimension = None;
+        for (number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            let mut fields = line.split_whitespace();
+            let Some(word) = fields.next() else {
+                continue;
+            };
+            let malformed = || format!("line {} is malformed", number + 1);
+            let vector: Vec<f32> = fields
+                .map(|e| e.parse::<f32>().map_err(|_| malformed()))
+                .collect::<Result<_, _>>()?;
+            if number == 0 && vector.len() == 1 && word.parse::<usize>().is_ok() {
+                continue;
+            }
+            if vector.is_empty() || dimension.is_some_and(|e| e != vector.len()) {
+                return Err(malformed());
+            }
+            dimension = Some(vector.len());
+            let norm = vector.iter().map(|e| e * e).sum::<f32>().sqrt();
+            if norm > 0.0 {
+                let vector = vector.into_iter().map(|e| e / norm).collect();
+                vectors.insert(escape(word).into_owned(), vector);
+            }
+        }
+        Ok(Self {
+            vectors,
+            known: Vec::new(),
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Self {
+        read_or_exit(path, "embeddings", Self::read)
+    }
+
+    /// sets the words with a vector that unknown words can be mapped to
+    pub fn set_known(&mut self, is_known: impl Fn(&str) -> bool) {
+        self.known = self
+            .vectors
+            .keys()
+            .filter(|e| is_known(e))
+            .cloned()
+            .collect();
+        self.known.sort();
+    }
+
+    /// returns up to `count` known words with the highest positive cosine similarity to the
+    /// word, the most similar one first
+    pub fn nearest(&self, word: &str, count: usize) -> Vec<(&str, f32)> {
+        let Some(vector) = self.vectors.get(word) else {
+            return Vec::new();
+        };
+        let mut similarities: Vec<(&str, f32)> = self
+            .known
+            .iter()
+            .map(|known| {
+                let similarity = self.vectors[known]
+                    .iter()
+                    .zip(vector)
+                    .map(|(a, b)| a * b)
+                    .sum();
+                (&known[..], similarity)
+            })
+            .filter(|(_, similarity)| *similarity > 0.0)
+            .collect();
+        similarities.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(b.0)));
+        similarities.truncate(count);
+        similarities
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nearest_test() {
+        let vectors = "4 2\ndog 1 0\ncat 0.9 0.1\ncar 0 1\n:-( 1 1\n";
+        let mut embeddings = Embeddings::read(vectors.as_bytes()).unwrap();
+        embeddings.set_known(|word| word != "dog");
+        let nearest = embeddings.nearest("dog", 2);
+        assert_eq!(
+            nearest.iter().map(|e| e.0).collect::<Vec<_>>(),
+            vec!["cat", ":-\\("]
+        );
+        assert!(nearest[0].1 > nearest[1].1);
+        assert!(embeddings.nearest("bird", 2).is_empty());
+        assert!(Embeddings::read("dog 1 0\ncat 1\n".as_bytes()).is_err());
+    }
+}
@@ -20,6 +20,11 @@ impl StringLookup {
         self.data.len()
     }
 
+    /// removes the strings that were inserted after the first `len`
+    pub fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+    }
+
     pub fn get_string(&self, index: usize) -> std::option::Option<&std::string::String> {
         self.data.get_index(index)
     }
@@ -419,7 +419,7 @@ mod test {
         let initial = Item::NonTerminal(string_map.get("ROOT").unwrap() as u32);
         grammar.entry(initial).or_default();
 
-        let line = transform_sentence("T S", &string_map, &false, &false).unwrap();
+        let line = transform_sentence("T S", &string_map, &false, None, &[], false).unwrap();
         let weight_map = deduce(&line, &grammar, None, initial, string_map.len());
         let tree = weight_map.convert_to_parse_tree(
             initial,
@@ -476,7 +476,7 @@ mod test {
         }
         let initial = Item::NonTerminal(string_map.get("ROOT").unwrap() as u32);
         grammar.entry(initial).or_default();
-        let line = transform_sentence("R S T", &string_map, &false, &false).unwrap();
+        let line = transform_sentence("R S T", &string_map, &false, None, &[], false).unwrap();
         let weight_map = deduce(&line, &grammar, None, initial, string_map.len());
         weight_map.convert_to_parse_tree(
             initial,
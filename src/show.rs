@@ -0,0 +1,385 @@
+use std::{io, process::exit};
+
+use foldhash::{HashMap, HashMapExt};
+
+use crate::induce::parse_tree::{ParseTree, open_trees, read_trees, unescape};
+
+const HIGHLIGHT: &str = "\x1b[1;31m";
+const RESET: &str = "\x1b[0m";
+
+/// the characters that connect a node with its children
+struct Lines {
+    horizontal: char,
+    vertical: char,
+    left: char,
+    right: char,
+    down: char,
+    up: char,
+    cross: char,
+}
+
+const UNICODE: Lines = Lines {
+    horizontal: '─',
+    vertical: '│',
+    left: '┌',
+    right: '┐',
+    down: '┬',
+    up: '┴',
+    cross: '┼',
+};
+
+const ASCII: Lines = Lines {
+    horizontal: '-',
+    vertical: '|',
+    left: '+',
+    right: '+',
+    down: '+',
+    up: '+',
+    cross: '+',
+};
+
+pub fn show(gold: &Option<std::path::PathBuf>, ascii: bool) {
+    let mut gold_trees = gold.as_ref().map(|path| open_trees(path));
+    for tree_text in read_trees(io::stdin().lock(), "stdin") {
+        let gold = gold_trees.as_mut().map(|gold_trees| {
+            gold_trees.next().unwrap_or_else(|| {
+                eprintln!("the gold file has fewer trees than stdin");
+                exit(1);
+            })
+        });
+        // the sentences the parser failed on are written as they are
+        let tree = match tree_text.parse() {
+            Ok(tree) => tree,
+            Err(_) if is_noparse(&tree_text.text) => {
+                println!("{}", tree_text.text.trim());
+                continue;
+            }
+            Err(e) => {
+                eprintln!("stdin, {}", e);
+                exit(1);
+            }
+        };
+        let highlighted = match &gold {
+            Some(gold) => differing_nodes(&tree, &gold.parse_or_exit("gold")),
+            None => vec![false; count_nodes(&tree)],
+        };
+        println!("{}", draw(&tree, &highlighted, ascii));
+    }
+}
+
+/// whether the line is a `(NOPARSE w1 w2 …)` line of the parser
+fn is_noparse(text: &str) -> bool {
+    text.trim()
+        .strip_prefix("(NOPARSE")
+        .is_some_and(|rest| rest.starts_with(char::is_whitespace) && rest.ends_with(')'))
+}
+
+fn count_nodes<T>(tree: &ParseTree<T>) -> usize {
+    let mut count = 0;
+    tree.execute_for_nodes(&mut |_| count += 1);
+    count
+}
+
+/// collects `(label, start, end)` of all nodes in pre-order, words are `None`
+pub fn brackets<'a, T: AsRef<str>>(
+    tree: &'a ParseTree<T>,
+    position: &mut usize,
+    brackets: &mut Vec<Option<(&'a str, usize, usize)>>,
+) {
+    if tree.is_leaf() {
+        *position += 1;
+        brackets.push(None);
+        return;
+    }
+    let index = brackets.len();
+    brackets.push(None);
+    let start = *position;
+    for child in &tree.children {
+        self::brackets(child, position, brackets);
+    }
+    brackets[index] = Some((tree.root.as_ref(), start, *position));
+}
+
+/// returns for every node in pre-order whether it is a constituent that the gold tree lacks
+pub fn differing_nodes<T: AsRef<str>, G: AsRef<str>>(
+    tree: &ParseTree<T>,
+    gold: &ParseTree<G>,
+) -> Vec<bool> {
+    let mut gold_brackets = Vec::new();
+    self::brackets(gold, &mut 0, &mut gold_brackets);
+    let mut remaining: HashMap<(&str, usize, usize), usize> = HashMap::new();
+    for bracket in gold_brackets.into_iter().flatten() {
+        *remaining.entry(bracket).or_default() += 1;
+    }
+    let mut tree_brackets = Vec::new();
+    self::brackets(tree, &mut 0, &mut tree_brackets);
+    tree_brackets
+        .into_iter()
+        .map(|bracket| match bracket {
+            Some(bracket) => match remaining.get_mut(&bracket).filter(|e| **e > 0) {
+                Some(count) => {
+                    *count -= 1;
+                    false
+                }
+                None => true,
+            },
+            None => false,
+        })
+        .collect()
+}
+
+/// a node of the drawing
+struct Placed {
+    text: String,
+    depth: usize,
+    center: usize,
+    children: Vec<usize>,
+    highlighted: bool,
+}
+
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// returns the width of the column of each word: the word and all labels that span only this
+/// word have to fit into it
+fn column_widths<T: AsRef<str>>(tree: &ParseTree<T>, above: usize, widths: &mut Vec<usize>) {
+    if tree.is_leaf() {
+        widths.push(above.max(width(&unescape(tree.root.as_ref()))));
+        return;
+    }
+    let label = width(tree.root.as_ref());
+    if tree.children.len() == 1 {
+        column_widths(&tree.children[0], above.max(label), widths);
+    } else {
+        for child in &tree.children {
+            column_widths(child, 0, widths);
+        }
+    }
+}
+
+/// places the nodes in pre-order and returns the index of the root
+fn place<T: AsRef<str>>(
+    tree: &ParseTree<T>,
+    depth: usize,
+    centers: &[usize],
+    word: &mut usize,
+    highlighted: &[bool],
+    placed: &mut Vec<Placed>,
+) -> usize {
+    let index = placed.len();
+    placed.push(Placed {
+        text: if tree.is_leaf() {
+            unescape(tree.root.as_ref()).into_owned()
+        } else {
+            tree.root.as_ref().to_string()
+        },
+        depth,
+        center: 0,
+        children: Vec::new(),
+        highlighted: highlighted[index],
+    });
+    if tree.is_leaf() {
+        placed[index].center = centers[*word];
+        *word += 1;
+        return index;
+    }
+    let children: Vec<usize> = tree
+        .children
+        .iter()
+        .map(|child| place(child, depth + 1, centers, word, highlighted, placed))
+        .collect();
+    let first = placed[children[0]].center;
+    let last = placed[*children.last().unwrap()].center;
+    placed[index].center = (first + last) / 2;
+    placed[index].children = children;
+    index
+}
+
+/// draws the tree with the words aligned in the last line, the labels of `highlighted` nodes
+/// (in pre-order) are coloured
+pub fn draw<T: AsRef<str>>(tree: &ParseTree<T>, highlighted: &[bool], ascii: bool) -> String {
+    let lines = if ascii { &ASCII } else { &UNICODE };
+    let mut widths = Vec::new();
+    column_widths(tree, 0, &mut widths);
+    let mut spans = Vec::new();
+    brackets(tree, &mut 0, &mut spans);
+    // the first and last column under a label are widened until the label fits above them
+    let (placed, total_width) = loop {
+        let mut centers = Vec::with_capacity(widths.len());
+        let mut offset = 0;
+        for width in &widths {
+            centers.push(offset + width / 2);
+            offset += width + 2;
+        }
+        let total_width = offset.saturating_sub(2).max(1);
+        let mut placed = Vec::new();
+        place(tree, 0, &centers, &mut 0, highlighted, &mut placed);
+
+        let mut widened = false;
+        for (node, span) in placed.iter().zip(&spans) {
+            let Some((_, start, end)) = span else {
+                continue;
+            };
+            let text_width = width(&node.text);
+            let left = centers[*start] - widths[*start] / 2;
+            let right = centers[end - 1] - widths[end - 1] / 2 + widths[end - 1];
+            let missing_left = (text_width / 2).saturating_sub(node.center - left);
+            let missing_right = (node.center + text_width - text_width / 2).saturating_sub(right);
+            if missing_left > 0 {
+                widths[*start] += missing_left;
+            } else if missing_right > 0 {
+                widths[end - 1] += missing_right;
+            } else {
+                continue;
+            }
+            widened = true;
+            break;
+        }
+        if !widened {
+            break (placed, total_width);
+        }
+    };
+    let word_depth = placed
+        .iter()
+        .filter(|node| node.children.is_empty())
+        .map(|node| node.depth)
+        .max()
+        .unwrap_or(0);
+    let mut grid = vec![vec![' '; total_width]; 2 * word_depth + 1];
+    let mut colours: Vec<Vec<(usize, usize)>> = vec![Vec::new(); grid.len()];
+
+    for node in &placed {
+        // words are moved down to the last line
+        let row = if node.children.is_empty() {
+            for row in &mut grid[2 * node.depth..2 * word_depth] {
+                row[node.center] = lines.vertical;
+            }
+            2 * word_depth
+        } else {
+            2 * node.depth
+        };
+        let text_width = width(&node.text);
+        let start = node
+            .center
+            .saturating_sub(text_width / 2)
+            .min(total_width.saturating_sub(text_width));
+        for (i, c) in node.text.chars().enumerate() {
+            if let Some(cell) = grid[row].get_mut(start + i) {
+                *cell = c;
+            }
+        }
+        if node.highlighted {
+            colours[row].push((start, start + text_width));
+        }
+
+        if node.children.is_empty() {
+            continue;
+        }
+        let connector = &mut grid[row + 1];
+        let child_centers: Vec<usize> = node.children.iter().map(|e| placed[*e].center).collect();
+        let first = child_centers[0];
+        let last = *child_centers.last().unwrap();
+        for cell in &mut connector[first..=last] {
+            *cell = lines.horizontal;
+        }
+        for center in &child_centers {
+            connector[*center] = lines.down;
+        }
+        if child_centers.len() == 1 {
+            connector[first] = lines.vertical;
+        } else {
+            connector[first] = lines.left;
+            connector[last] = lines.right;
+            connector[node.center] = if child_centers.contains(&node.center) {
+                lines.cross
+            } else {
+                lines.up
+            };
+        }
+    }
+
+    grid.iter()
+        .zip(&colours)
+        .map(|(row, colours)| {
+            let mut line = String::new();
+            for (i, c) in row.iter().enumerate() {
+                if colours.iter().any(|(start, _)| *start == i) {
+                    line.push_str(HIGHLIGHT);
+                }
+                line.push(*c);
+                if colours.iter().any(|(_, end)| *end == i + 1) {
+                    line.push_str(RESET);
+                }
+            }
+            line.trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::induce::parse_tree::element;
+
+    #[test]
+    fn draw_test() {
+        let (_, tree) = element("(S (NP (DT the) (NN dog)) (VP (VBD barked)))").unwrap();
+        let highlighted = vec![false; count_nodes(&tree)];
+        assert_eq!(
+            draw(&tree, &highlighted, true),
+            [
+                "        S",
+                "   +----+----+",
+                "  NP        VP",
+                " +-+--+      |",
+                "DT   NN     VBD",
+                " |    |      |",
+                "the  dog  barked",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn long_label_test() {
+        let (_, tree) = element("(ROOT (NP|<DT,JJ,NN,VB,XX>^<S,ROOT> (DT the) (JJ big)))").unwrap();
+        let highlighted = vec![false; count_nodes(&tree)];
+        assert_eq!(
+            draw(&tree, &highlighted, true),
+            [
+                "              ROOT",
+                "                |",
+                "  NP|<DT,JJ,NN,VB,XX>^<S,ROOT>",
+                "        +-------+-------+",
+                "       DT              JJ",
+                "        |               |",
+                "       the             big",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn noparse_test() {
+        assert!(is_noparse("(NOPARSE the dog)"));
+        assert!(is_noparse(" (NOPARSE dog) "));
+        assert!(!is_noparse("(NOPARSEX the dog)"));
+        assert!(!is_noparse("(S (NP dog))"));
+    }
+
+    #[test]
+    fn differing_nodes_test() {
+        let (_, tree) = element("(S (NP (DT the) (NN dog)) (VP (VBD barked)))").unwrap();
+        let (_, gold) = element("(S (NP (DT the)) (VP (NN dog) (VBD barked)))").unwrap();
+        let differing = differing_nodes(&tree, &gold);
+        // S NP DT the NN dog VP VBD barked
+        assert_eq!(
+            differing,
+            vec![false, true, false, false, false, false, true, false, false]
+        );
+        let drawing = draw(&tree, &differing, false);
+        assert!(drawing.contains(&format!("{HIGHLIGHT}NP{RESET}")));
+    }
+}
@@ -1,42 +1,55 @@
-use std::{char, io};
-
-use foldhash::{HashMap, HashSet};
-
-use crate::{induce::parse_tree::ParseTree, unk::get_tree_lines};
-
-pub fn smooth(threshold: u64) {
-    let mut word_count: HashMap<&str, u64> = HashMap::default();
-    let string_lines: Vec<String> = io::stdin().lines().map_while(Result::ok).collect();
-    let trees = get_tree_lines(&string_lines, &mut word_count);
-    let unknown_words: HashSet<&str> = word_count
-        .into_iter()
-        .filter_map(
-            |(word, count)| {
-                if count <= threshold { Some(word) } else { None }
-            },
-        )
-        .collect();
-    for tree in trees {
-        let smoothed_tree = smooth_tree(tree, &unknown_words, true);
-        println!("{}", smoothed_tree);
-    }
+use std::{
+    char,
+    io::{self, BufRead},
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, ValueEnum};
+use foldhash::HashMap;
+
+use crate::{
+    induce::parse_tree::{ParseTree, escape, read_or_exit, unescape},
+    unk::{Vocabulary, rewrite_trees},
+};
+
+pub fn smooth(
+    threshold: u64,
+    vocabulary: &Option<PathBuf>,
+    inputs: &[PathBuf],
+    scheme: &SignatureScheme,
+) {
+    rewrite_trees(
+        io::stdin().lock(),
+        inputs,
+        vocabulary,
+        threshold,
+        |tree, vocabulary| {
+            let smoothed_tree = smooth_tree(tree, vocabulary, scheme, &mut 0);
+            println!("{}", smoothed_tree);
+        },
+    );
 }
 
-fn smooth_tree(
+/// `position` is the index of the first word of `tree` in the sentence, only the word at
+/// index 0 gets the sentence-initial signature, like in `parse`
+pub fn smooth_tree(
     tree: ParseTree<&str>,
-    unknown_words: &HashSet<&str>,
-    mut first: bool,
+    vocabulary: &Vocabulary,
+    scheme: &SignatureScheme,
+    position: &mut usize,
 ) -> ParseTree<String> {
     let mut smoothed_tree = ParseTree::new(tree.root.to_string());
-    if tree.is_leaf() && unknown_words.contains(&tree.root) {
-        smoothed_tree.root = smooth_word(tree.root, first);
+    if tree.is_leaf() {
+        if !vocabulary.contains(tree.root) {
+            smoothed_tree.root = scheme.signature(tree.root, *position == 0);
+        }
+        *position += 1;
     }
 
     for child in tree.children {
         smoothed_tree
             .children
-            .push(smooth_tree(child, unknown_words, first));
-        first = false
+            .push(smooth_tree(child, vocabulary, scheme, position));
     }
     smoothed_tree
 }
@@ -49,79 +62,258 @@ where
     word.chars().any(test)
 }
 
-pub fn smooth_word(word: &str, first: bool) -> String {
-    let first_char = word.chars().next();
-
-    // does this even happen?
-    let letter_suffix = if word.is_empty() {
-        return "UNK".to_string();
-    } else if first_char.is_some_and(char::is_uppercase) && !has_any(word, char::is_lowercase) {
-        // All Caps or numbers
-        "-AC"
-    } else if first_char.is_some_and(char::is_uppercase) {
-        // Capital word
-        if first { "-SC" } else { "-C" }
-    } else if has_any(word, char::is_lowercase) {
-        // has lowercase
-        "-L"
-    } else if has_any(word, char::is_alphabetic) {
-        // has letters
-        "-U"
-    } else {
-        // no letters
-        "-S"
-    };
-
-    let number_suffix = if word.chars().all(char::is_numeric) {
-        // is a number
-        "-N"
-    } else if has_any(word, char::is_numeric) {
-        // contains digits
-        "-n"
-    } else {
-        ""
-    };
-
-    let dash_suffix = if has_any(word, |e| e == '-') {
-        // contains a dash
-        "-H"
-    } else {
-        ""
-    };
-    let period_suffix = if has_any(word, |e| e == '.') {
-        // contains a dot
-        "-P"
-    } else {
-        ""
-    };
-
-    let comma_suffix = if has_any(word, |e| e == ',') {
-        // contains a comma
-        "-C"
-    } else {
-        ""
-    };
-
-    let last = word.chars().last();
-    let word_suffix = if word.len() > 3 && last.unwrap().is_alphabetic() {
-        // add the last character if it is a letter
-        &("-".to_string() + &last.unwrap().to_lowercase().to_string())[..]
-    } else {
-        ""
-    };
-
-    "UNK".to_string()
-        + letter_suffix
-        + number_suffix
-        + dash_suffix
-        + period_suffix
-        + comma_suffix
-        + word_suffix
+/// the suffixes that the signatures of level 5 keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Language {
+    /// no language specific suffixes, level 5 is the same as level 4
+    #[default]
+    Generic,
+    /// inflectional and derivational suffixes of English like the Berkeley parser
+    English,
+    /// inflectional and derivational suffixes of German
+    German,
+}
+
+const ENGLISH_SUFFIXES: [&str; 9] = ["ed", "ing", "ion", "er", "est", "ly", "ity", "y", "al"];
+const GERMAN_SUFFIXES: [&str; 12] = [
+    "ungen", "ung", "heit", "keit", "lich", "isch", "chen", "en", "er", "es", "e", "s",
+];
+
+/// word clusters from raw text like Brown clusters
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Clusters {
+    clusters: HashMap<String, String>,
+}
+
+impl Clusters {
+    /// reads `word<TAB>cluster` lines or the `cluster<TAB>word<TAB>count` lines of Brown
+    /// clusters
+    pub fn read(reader: impl BufRead) -> Result<Self, String> {
+        let mut clusters = HashMap::default();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (word, cluster) = match fields[..] {
+                [word, cluster] => (word, cluster),
+                [cluster, word, _] => (word, cluster),
+                _ => return Err(format!("line {} is malformed: {:?}", number + 1, line)),
+            };
+            clusters.insert(word.to_string(), cluster.to_string());
+        }
+        Ok(Self { clusters })
+    }
+
+    pub fn from_file(path: &Path) -> Self {
+        read_or_exit(path, "clusters", Self::read)
+    }
+
+    /// the cluster of a word in its escaped form
+    pub fn cluster(&self, word: &str) -> Option<&str> {
+        self.clusters.get(&unescape(word)[..]).map(|e| &e[..])
+    }
+}
+
+/// how unknown words are mapped to signatures like `UNK-SC-n`
+///
+/// every level adds features to the one below: 0 is `UNK`, 1 the letter class, 2 digits, 3
+/// dashes, periods and commas, 4 the last `suffix` letters and 5 a suffix of the language
+/// instead of them
+///
+/// words with a cluster get `UNK-CLUSTER-<id>` instead
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureScheme {
+    pub level: u8,
+    pub suffix: usize,
+    pub language: Language,
+    pub clusters: Option<Clusters>,
+}
+
+impl Default for SignatureScheme {
+    fn default() -> Self {
+        Self {
+            level: 4,
+            suffix: 1,
+            language: Language::Generic,
+            clusters: None,
+        }
+    }
+}
+
+// the signature options shared by `smooth`, `parse` and `train-em`
+#[derive(Args, Clone, Debug)]
+pub struct SignatureOptions {
+    /// how detailed the signatures of unknown words are, from 0 (UNK) to 5 (language suffixes)
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u8).range(0..=5))]
+    pub signature_level: u8,
+    /// number of last letters in the signatures from level 4
+    #[arg(long, default_value_t = 1)]
+    pub suffix_length: usize,
+    /// the suffixes of the signatures of level 5
+    #[arg(long, value_enum, default_value_t = Language::Generic)]
+    pub language: Language,
+    /// map words with a cluster in this `word<TAB>cluster` or Brown cluster file to
+    /// UNK-CLUSTER-<id>
+    #[arg(long, value_name = "CLUSTERS")]
+    pub clusters: Option<PathBuf>,
+}
+
+impl SignatureOptions {
+    pub fn scheme(&self) -> SignatureScheme {
+        SignatureScheme {
+            level: self.signature_level,
+            suffix: self.suffix_length,
+            language: self.language,
+            clusters: self.clusters.as_deref().map(Clusters::from_file),
+        }
+    }
+}
+
+impl SignatureScheme {
+    pub fn signature(&self, word: &str, first: bool) -> String {
+        self.cluster_signature(word)
+            .unwrap_or_else(|| self.signature_at(word, first, self.level))
+    }
+
+    /// returns the signatures from the cluster and the level of the scheme down to `UNK`, each
+    /// one is coarser than the one before
+    pub fn signatures(&self, word: &str, first: bool) -> Vec<String> {
+        let mut signatures: Vec<String> = self.cluster_signature(word).into_iter().collect();
+        signatures.extend(
+            (0..=self.level)
+                .rev()
+                .map(|level| self.signature_at(word, first, level)),
+        );
+        signatures.dedup();
+        signatures
+    }
+
+    fn cluster_signature(&self, word: &str) -> Option<String> {
+        let cluster = self.clusters.as_ref()?.cluster(word)?;
+        Some(format!("UNK-CLUSTER-{}", escape(cluster)))
+    }
+
+    fn signature_at(&self, word: &str, first: bool, level: u8) -> String {
+        let word = &unescape(word)[..];
+        let first_char = word.chars().next();
+
+        // does this even happen?
+        let letter_suffix = if word.is_empty() || level == 0 {
+            return "UNK".to_string();
+        } else if first_char.is_some_and(char::is_uppercase) && !has_any(word, char::is_lowercase) {
+            // All Caps or numbers
+            "-AC"
+        } else if first_char.is_some_and(char::is_uppercase) {
+            // Capital word
+            if first { "-SC" } else { "-C" }
+        } else if has_any(word, char::is_lowercase) {
+            // has lowercase
+            "-L"
+        } else if has_any(word, char::is_alphabetic) {
+            // has letters
+            "-U"
+        } else {
+            // no letters
+            "-S"
+        };
+        let mut signature = "UNK".to_string() + letter_suffix;
+        if level < 2 {
+            return signature;
+        }
+
+        signature += if word.chars().all(char::is_numeric) {
+            // is a number
+            "-N"
+        } else if has_any(word, char::is_numeric) {
+            // contains digits
+            "-n"
+        } else {
+            ""
+        };
+        if level < 3 {
+            return signature;
+        }
+
+        if has_any(word, |e| e == '-') {
+            // contains a dash
+            signature += "-H";
+        }
+        if has_any(word, |e| e == '.') {
+            // contains a dot
+            signature += "-P";
+        }
+        if has_any(word, |e| e == ',') {
+            // contains a comma
+            signature += "-C";
+        }
+        if level < 4 {
+            return signature;
+        }
+
+        let language_suffix = if level >= 5 {
+            self.language_suffix(word)
+        } else {
+            None
+        };
+        if let Some(suffix) = language_suffix {
+            signature += "-";
+            signature += suffix;
+        } else if let Some(suffix) = self.last_letters(word) {
+            // add the last letters
+            signature += "-";
+            signature += &suffix;
+        }
+        signature
+    }
+
+    /// the last `suffix` letters in lowercase if the word is longer than them and two more
+    /// characters
+    fn last_letters(&self, word: &str) -> Option<String> {
+        let length = word.chars().count();
+        if self.suffix == 0 || length <= self.suffix + 2 {
+            return None;
+        }
+        let last: String = word.chars().skip(length - self.suffix).collect();
+        last.chars()
+            .all(char::is_alphabetic)
+            .then(|| last.to_lowercase())
+    }
+
+    fn language_suffix(&self, word: &str) -> Option<&'static str> {
+        let lower = word.to_lowercase();
+        let length = lower.chars().count();
+        match self.language {
+            Language::Generic => None,
+            Language::English => {
+                let before_last = lower.chars().rev().nth(1);
+                if lower.ends_with('s')
+                    && length >= 3
+                    && !before_last.is_some_and(|e| "siu".contains(e))
+                {
+                    return Some("s");
+                }
+                let mixed = has_any(word, char::is_numeric) && has_any(word, char::is_uppercase);
+                if length < 5 || has_any(word, |e| e == '-') || mixed {
+                    return None;
+                }
+                ENGLISH_SUFFIXES.into_iter().find(|e| lower.ends_with(e))
+            }
+            Language::German => {
+                if length < 5 || has_any(word, |e| e == '-') {
+                    return None;
+                }
+                GERMAN_SUFFIXES.into_iter().find(|e| lower.ends_with(e))
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::induce::parse_tree::element;
     const UNKNOWN_WORDS: [&str; 7] = ["test1", "1984", "CAPS", "a,", "a.", "long_word", "Capital"];
     const TRANSLATED_WORDS: [&str; 7] = [
         "UNK-L-n", "UNK-S-N", "UNK-AC-s", "UNK-L-C", "UNK-L-P", "UNK-L-d", "UNK-SC-l",
@@ -129,7 +321,7 @@ mod test {
 
     #[test]
     fn test_some_input() {
-        let unknown_words = HashSet::from_iter(UNKNOWN_WORDS);
+        let vocabulary = Vocabulary::from_counts([("test", 1)], 0);
         for (input, output) in UNKNOWN_WORDS.into_iter().zip(TRANSLATED_WORDS) {
             let input_tree = ParseTree {
                 root: "ROOT",
@@ -143,14 +335,14 @@ mod test {
                 ],
             };
 
-            let tree = smooth_tree(input_tree, &unknown_words, true);
+            let tree = smooth_tree(input_tree, &vocabulary, &SignatureScheme::default(), &mut 0);
             assert_eq!(desired_tree, tree);
         }
     }
 
     #[test]
     fn test_first_capital() {
-        let unknown_words = HashSet::from_iter(["Try"]);
+        let vocabulary = Vocabulary::from_counts([("test", 1)], 0);
         let input_tree = ParseTree {
             root: "ROOT",
             children: vec![ParseTree::new("Try"), ParseTree::new("test")],
@@ -162,7 +354,7 @@ mod test {
                 ParseTree::new("test".to_string()),
             ],
         };
-        let tree = smooth_tree(input_tree, &unknown_words, true);
+        let tree = smooth_tree(input_tree, &vocabulary, &SignatureScheme::default(), &mut 0);
         assert_eq!(desired_tree, tree);
 
         let input_tree = ParseTree {
@@ -176,7 +368,61 @@ mod test {
                 ParseTree::new("UNK-C".to_string()),
             ],
         };
-        let tree = smooth_tree(input_tree, &unknown_words, true);
+        let tree = smooth_tree(input_tree, &vocabulary, &SignatureScheme::default(), &mut 0);
         assert_eq!(desired_tree, tree);
+
+        // only the first word of the yield is sentence-initial, not the first words of the
+        // constituents
+        let (_, input_tree) =
+            element("(ROOT (S (NP (NNP Try) (NNP Try)) (VP (VB test) (NP (NNP Try)))))").unwrap();
+        let tree = smooth_tree(input_tree, &vocabulary, &SignatureScheme::default(), &mut 0);
+        assert_eq!(
+            tree.to_string(),
+            "(ROOT (S (NP (NNP UNK-SC) (NNP UNK-C)) (VP (VB test) (NP (NNP UNK-C)))))"
+        );
+    }
+
+    #[test]
+    fn signature_levels_test() {
+        let scheme = SignatureScheme {
+            level: 5,
+            suffix: 2,
+            language: Language::English,
+            clusters: None,
+        };
+        assert_eq!(
+            scheme.signatures("Walking-3", false),
+            vec!["UNK-C-n-H", "UNK-C-n", "UNK-C", "UNK"]
+        );
+        assert_eq!(scheme.signature("walking", false), "UNK-L-ing");
+        assert_eq!(scheme.signature("dogs", false), "UNK-L-s");
+        assert_eq!(scheme.signature("abcdef", false), "UNK-L-ef");
+        let scheme = SignatureScheme {
+            language: Language::German,
+            ..scheme
+        };
+        assert_eq!(scheme.signature("Zeitung", true), "UNK-SC-ung");
+        let scheme = SignatureScheme {
+            level: 1,
+            ..SignatureScheme::default()
+        };
+        assert_eq!(scheme.signatures("1984", true), vec!["UNK-S", "UNK"]);
+    }
+
+    #[test]
+    fn clusters_test() {
+        let clusters = "dog\t0110\ncat\t0110\n10\tNew York\t12\n";
+        let scheme = SignatureScheme {
+            clusters: Some(Clusters::read(clusters.as_bytes()).unwrap()),
+            ..SignatureScheme::default()
+        };
+        assert_eq!(scheme.signature("dog", false), "UNK-CLUSTER-0110");
+        assert_eq!(scheme.signature("New\\ York", true), "UNK-CLUSTER-10");
+        assert_eq!(scheme.signature("Dog", true), "UNK-SC");
+        assert_eq!(
+            scheme.signatures("cat", false),
+            vec!["UNK-CLUSTER-0110", "UNK-L", "UNK"]
+        );
+        assert!(Clusters::read("dog 0110\n".as_bytes()).is_err());
     }
 }
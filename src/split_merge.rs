@@ -0,0 +1,791 @@
+use std::{borrow::Cow, io, path::Path, process::exit, thread};
+
+use foldhash::{HashMap, HashMapExt};
+
+use crate::{
+    induce::{self, grammar_locations, parse_tree::ParseTree, parse_tree::read_trees, write_grammar},
+    markers::DEFAULT_MARKERS,
+    parse::{parse_rules, rule::Rhs, string_lookup::StringLookup, weight_map::Item},
+    random::Random,
+};
+
+/// relative amount of noise added to the weights of split rules to break the symmetry
+const SPLIT_NOISE: f64 = 0.01;
+
+#[allow(clippy::too_many_arguments)]
+pub fn split_merge(
+    rules: &Path,
+    lexicon: &Path,
+    grammar: &Option<String>,
+    initial_nonterminal: &str,
+    cycles: u64,
+    iterations: u64,
+    merge_fraction: f64,
+    seed: u64,
+) {
+    let mut string_lookup = StringLookup::default();
+    let mut rule_lookup = HashMap::new();
+    let mut all_rules = HashMap::new();
+    parse_rules(
+        &mut string_lookup,
+        &mut rule_lookup,
+        &mut all_rules,
+        rules,
+        true,
+    );
+    parse_rules(
+        &mut string_lookup,
+        &mut rule_lookup,
+        &mut all_rules,
+        lexicon,
+        false,
+    );
+    let Some(initial_nonterminal) = string_lookup.get(initial_nonterminal) else {
+        eprintln!("initial nonterminal is not in the rules");
+        exit(1);
+    };
+    let mut latent = LatentGrammar::new(
+        &all_rules,
+        &string_lookup,
+        Item::NonTerminal(initial_nonterminal as u32),
+    );
+
+    let mut trees = Vec::new();
+    for tree_text in read_trees(io::stdin().lock(), "stdin") {
+        let tree = tree_text.parse_or_exit("stdin");
+        match latent.tree_nodes(&tree) {
+            Ok(nodes) => trees.push(nodes),
+            Err(e) => {
+                eprintln!("stdin, {}", tree_text.error(&e));
+                exit(1);
+            }
+        }
+    }
+
+    let mut random = Random::new(seed);
+    for cycle in 1..=cycles {
+        latent.split(&mut random);
+        for iteration in 1..=iterations {
+            let expectation = latent.expectation(&trees, None);
+            eprintln!(
+                "cycle {} split iteration {}: log-likelihood {}",
+                cycle, iteration, expectation.log_likelihood
+            );
+            latent.maximize(&expectation.counts);
+        }
+        let expectation = latent.expectation(&trees, None);
+        let losses = latent.expectation(&trees, Some(&expectation.frequencies));
+        latent.merge(&expectation.counts, &losses.merge_losses, merge_fraction);
+        for iteration in 1..=iterations {
+            let expectation = latent.expectation(&trees, None);
+            eprintln!(
+                "cycle {} merge iteration {}: log-likelihood {}",
+                cycle, iteration, expectation.log_likelihood
+            );
+            latent.maximize(&expectation.counts);
+        }
+        eprintln!(
+            "cycle {}: {} subcategories",
+            cycle,
+            latent.splits.iter().sum::<usize>()
+        );
+    }
+
+    let (mut rules, mut lexicon, mut words) = grammar_locations(grammar);
+    write_grammar(&mut rules, &mut lexicon, &mut words, &latent.to_grammar());
+}
+
+/// returns the label of a latent subcategory `NP_3` or of a symbol that was not split
+///
+/// `_` and `\` of the original labels are escaped with a backslash, so `NP\_1_3` is a
+/// subcategory of `NP_1`
+pub fn latent_label(label: &str, subcategory: Option<usize>) -> String {
+    let mut latent = String::with_capacity(label.len() + 3);
+    for c in label.chars() {
+        if c == '_' || c == '\\' {
+            latent.push('\\');
+        }
+        latent.push(c);
+    }
+    if let Some(subcategory) = subcategory {
+        latent += &format!("_{subcategory}");
+    }
+    latent
+}
+
+/// returns the label of the original grammar for a label written by [`latent_label`]
+pub fn project(label: &str) -> Cow<'_, str> {
+    let mut separator = None;
+    let mut escaped = false;
+    for (i, c) in label.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '_' => separator = Some(i),
+            _ => {}
+        }
+    }
+    let base = match separator {
+        Some(i)
+            if i > 0
+                && label.len() > i + 1
+                && label[i + 1..].chars().all(|e| e.is_ascii_digit()) =>
+        {
+            &label[..i]
+        }
+        _ => label,
+    };
+    DEFAULT_MARKERS.unescape(base)
+}
+
+/// a rule of the original grammar with the weights of all combinations of subcategories
+///
+/// the weight of `lhs_a -> left_b right_c` is at `(a * |left| + b) * |right| + c`
+struct LatentRule {
+    lhs: usize,
+    /// empty for lexical rules
+    children: Vec<usize>,
+    /// the terminal of lexical rules
+    word: Option<String>,
+    weights: Vec<f64>,
+}
+
+impl LatentRule {
+    /// returns the number of subcategories of the left hand side and of each child
+    fn sizes(&self, splits: &[usize]) -> Vec<usize> {
+        std::iter::once(self.lhs)
+            .chain(self.children.iter().copied())
+            .map(|e| splits[e])
+            .collect()
+    }
+}
+
+/// a node of a training tree with the index of the rule that is applied at it and the indices
+/// of its children, the nodes of a tree are in post-order
+struct Node {
+    rule: usize,
+    children: Vec<usize>,
+}
+
+struct Expectation {
+    /// the expected count of each subcategory combination of each rule
+    counts: Vec<Vec<f64>>,
+    log_likelihood: f64,
+    /// the expected count of each subcategory of each symbol
+    frequencies: Vec<Vec<f64>>,
+    /// the log-likelihood change when merging the pairs of subcategories of each symbol
+    merge_losses: Vec<Vec<f64>>,
+}
+
+impl Expectation {
+    fn new(grammar: &LatentGrammar) -> Self {
+        Self {
+            counts: grammar
+                .rules
+                .iter()
+                .map(|rule| vec![0.0; rule.weights.len()])
+                .collect(),
+            log_likelihood: 0.0,
+            frequencies: grammar.splits.iter().map(|k| vec![0.0; *k]).collect(),
+            merge_losses: grammar.splits.iter().map(|k| vec![0.0; k / 2]).collect(),
+        }
+    }
+
+    fn add(&mut self, other: Expectation) {
+        let add_all = |own: &mut Vec<Vec<f64>>, other: Vec<Vec<f64>>| {
+            for (own, other) in own.iter_mut().zip(other) {
+                for (own, other) in own.iter_mut().zip(other) {
+                    *own += other;
+                }
+            }
+        };
+        add_all(&mut self.counts, other.counts);
+        add_all(&mut self.frequencies, other.frequencies);
+        add_all(&mut self.merge_losses, other.merge_losses);
+        self.log_likelihood += other.log_likelihood;
+    }
+}
+
+/// a grammar whose symbols are split into latent subcategories
+struct LatentGrammar {
+    names: Vec<String>,
+    /// number of subcategories of each symbol
+    splits: Vec<usize>,
+    start: usize,
+    rules: Vec<LatentRule>,
+    binary: HashMap<(usize, usize, usize), usize>,
+    unary: HashMap<(usize, usize), usize>,
+    lexical: HashMap<(usize, String), usize>,
+}
+
+impl LatentGrammar {
+    fn new(
+        all_rules: &HashMap<Item, HashMap<Rhs<Item>, f64>>,
+        string_lookup: &StringLookup,
+        start: Item,
+    ) -> Self {
+        let mut grammar = Self {
+            names: vec![],
+            splits: vec![],
+            start: 0,
+            rules: vec![],
+            binary: HashMap::new(),
+            unary: HashMap::new(),
+            lexical: HashMap::new(),
+        };
+        let mut symbols: HashMap<Item, usize> = HashMap::new();
+        let mut symbol = |grammar: &mut Self, item: Item| {
+            *symbols.entry(item).or_insert_with(|| {
+                grammar.names.push(name(string_lookup, item));
+                grammar.splits.push(1);
+                grammar.names.len() - 1
+            })
+        };
+        grammar.start = symbol(&mut grammar, start);
+        let mut sorted_rules: Vec<(&Item, &HashMap<Rhs<Item>, f64>)> = all_rules.iter().collect();
+        sorted_rules.sort_by_key(|(lhs, _)| **lhs);
+        for (lhs, bodies) in sorted_rules {
+            let lhs = symbol(&mut grammar, *lhs);
+            let mut bodies: Vec<(&Rhs<Item>, &f64)> = bodies.iter().collect();
+            bodies.sort_by_key(|(rhs, _)| **rhs);
+            for (rhs, weight) in bodies {
+                let index = grammar.rules.len();
+                let (children, word) = match *rhs {
+                    Rhs::Binary(left, right) => {
+                        let children =
+                            vec![symbol(&mut grammar, left), symbol(&mut grammar, right)];
+                        grammar
+                            .binary
+                            .insert((lhs, children[0], children[1]), index);
+                        (children, None)
+                    }
+                    Rhs::Unary(child @ Item::NonTerminal(_)) => {
+                        let children = vec![symbol(&mut grammar, child)];
+                        grammar.unary.insert((lhs, children[0]), index);
+                        (children, None)
+                    }
+                    Rhs::Unary(terminal @ Item::Terminal(_)) => {
+                        let word = name(string_lookup, terminal);
+                        grammar.lexical.insert((lhs, word.clone()), index);
+                        (vec![], Some(word))
+                    }
+                };
+                grammar.rules.push(LatentRule {
+                    lhs,
+                    children,
+                    word,
+                    weights: vec![*weight],
+                });
+            }
+        }
+        grammar
+    }
+
+    /// returns the nodes of the `tree` in post-order or an error if the tree uses a rule that is
+    /// not in the grammar
+    fn tree_nodes(&self, tree: &ParseTree<&str>) -> Result<Vec<Node>, String> {
+        let symbols: HashMap<&str, usize> = self
+            .names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (&name[..], index))
+            .collect();
+        let mut nodes = Vec::new();
+        self.add_nodes(tree, &symbols, &mut nodes)?;
+        Ok(nodes)
+    }
+
+    fn add_nodes(
+        &self,
+        tree: &ParseTree<&str>,
+        symbols: &HashMap<&str, usize>,
+        nodes: &mut Vec<Node>,
+    ) -> Result<usize, String> {
+        let symbol = |label: &str| {
+            symbols
+                .get(label)
+                .copied()
+                .ok_or_else(|| format!("{label} is not in the grammar"))
+        };
+        let lhs = symbol(tree.root)?;
+        let (rule, children) = match &tree.children[..] {
+            [word] if word.is_leaf() => {
+                let rule = self
+                    .lexical
+                    .get(&(lhs, word.root.to_string()))
+                    .ok_or_else(|| format!("{} {} is not in the lexicon", tree.root, word.root))?;
+                (*rule, vec![])
+            }
+            [child] => {
+                let rule = self.unary.get(&(lhs, symbol(child.root)?)).ok_or_else(|| {
+                    format!("{} -> {} is not in the rules", tree.root, child.root)
+                })?;
+                let child = self.add_nodes(child, symbols, nodes)?;
+                (*rule, vec![child])
+            }
+            [left, right] => {
+                let rule = self
+                    .binary
+                    .get(&(lhs, symbol(left.root)?, symbol(right.root)?))
+                    .ok_or_else(|| {
+                        format!(
+                            "{} -> {} {} is not in the rules",
+                            tree.root, left.root, right.root
+                        )
+                    })?;
+                let left = self.add_nodes(left, symbols, nodes)?;
+                let right = self.add_nodes(right, symbols, nodes)?;
+                (*rule, vec![left, right])
+            }
+            _ => return Err("the trees have to be binarised".to_string()),
+        };
+        nodes.push(Node { rule, children });
+        Ok(nodes.len() - 1)
+    }
+
+    /// splits every symbol except the start symbol into twice as many subcategories
+    fn split(&mut self, random: &mut Random) {
+        let factors: Vec<usize> = (0..self.splits.len())
+            .map(|symbol| if symbol == self.start { 1 } else { 2 })
+            .collect();
+        for rule in &mut self.rules {
+            let old_sizes = rule.sizes(&self.splits);
+            let new_sizes: Vec<usize> = old_sizes
+                .iter()
+                .zip(std::iter::once(&rule.lhs).chain(&rule.children))
+                .map(|(size, symbol)| size * factors[*symbol])
+                .collect();
+            let children_factor: usize = rule.children.iter().map(|e| factors[*e]).product();
+            let mut weights = vec![0.0; new_sizes.iter().product()];
+            for (index, weight) in weights.iter_mut().enumerate() {
+                let old_index = project_index(index, &new_sizes, &old_sizes);
+                let noise = 1.0 + SPLIT_NOISE * (2.0 * random.next_f64() - 1.0);
+                *weight = rule.weights[old_index] / children_factor as f64 * noise;
+            }
+            rule.weights = weights;
+        }
+        for (split, factor) in self.splits.iter_mut().zip(&factors) {
+            *split *= factor;
+        }
+        self.normalize();
+    }
+
+    /// merges the given fraction of subcategory pairs whose merge loses the least likelihood
+    fn merge(&mut self, counts: &[Vec<f64>], merge_losses: &[Vec<f64>], merge_fraction: f64) {
+        let mut candidates: Vec<(f64, usize, usize)> = merge_losses
+            .iter()
+            .enumerate()
+            .filter(|(symbol, _)| *symbol != self.start)
+            .flat_map(|(symbol, losses)| {
+                losses
+                    .iter()
+                    .enumerate()
+                    .map(move |(pair, loss)| (*loss, symbol, pair))
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let merges = (candidates.len() as f64 * merge_fraction) as usize;
+        let mut merged: Vec<Vec<bool>> =
+            merge_losses.iter().map(|e| vec![false; e.len()]).collect();
+        for (_, symbol, pair) in &candidates[..merges] {
+            merged[*symbol][*pair] = true;
+        }
+
+        // maps each old subcategory to its new subcategory
+        let mut mappings: Vec<Vec<usize>> = Vec::with_capacity(self.splits.len());
+        for (symbol, split) in self.splits.iter_mut().enumerate() {
+            let mut mapping = Vec::with_capacity(*split);
+            let mut next = 0;
+            for subcategory in 0..*split {
+                if subcategory % 2 == 1 && merged[symbol].get(subcategory / 2) == Some(&true) {
+                    mapping.push(next - 1);
+                } else {
+                    mapping.push(next);
+                    next += 1;
+                }
+            }
+            *split = next;
+            mappings.push(mapping);
+        }
+
+        for (rule, counts) in self.rules.iter_mut().zip(counts) {
+            let symbols: Vec<usize> = std::iter::once(rule.lhs)
+                .chain(rule.children.iter().copied())
+                .collect();
+            let old_sizes: Vec<usize> = symbols.iter().map(|e| mappings[*e].len()).collect();
+            let new_sizes: Vec<usize> = symbols.iter().map(|e| self.splits[*e]).collect();
+            let mut weights = vec![0.0; new_sizes.iter().product()];
+            for (index, count) in counts.iter().enumerate() {
+                let mut old = unravel(index, &old_sizes);
+                for (subcategory, symbol) in old.iter_mut().zip(&symbols) {
+                    *subcategory = mappings[*symbol][*subcategory];
+                }
+                weights[ravel(&old, &new_sizes)] += count;
+            }
+            rule.weights = weights;
+        }
+        self.normalize();
+    }
+
+    /// sets the weights to the expected counts normalised over each left hand side subcategory
+    fn maximize(&mut self, counts: &[Vec<f64>]) {
+        for (rule, counts) in self.rules.iter_mut().zip(counts) {
+            rule.weights.clone_from(counts);
+        }
+        self.normalize();
+    }
+
+    fn normalize(&mut self) {
+        let mut totals: Vec<Vec<f64>> = self.splits.iter().map(|k| vec![0.0; *k]).collect();
+        for rule in &self.rules {
+            let per_subcategory = rule.weights.len() / self.splits[rule.lhs];
+            for (index, weight) in rule.weights.iter().enumerate() {
+                totals[rule.lhs][index / per_subcategory] += weight;
+            }
+        }
+        for rule in &mut self.rules {
+            let per_subcategory = rule.weights.len() / self.splits[rule.lhs];
+            for (index, weight) in rule.weights.iter_mut().enumerate() {
+                let total = totals[rule.lhs][index / per_subcategory];
+                if total > 0.0 {
+                    *weight /= total;
+                }
+            }
+        }
+    }
+
+    /// computes the expected counts over all `trees` in parallel, the merge losses are only
+    /// computed when the subcategory `frequencies` are given
+    fn expectation(&self, trees: &[Vec<Node>], frequencies: Option<&[Vec<f64>]>) -> Expectation {
+        let workers = thread::available_parallelism()
+            .map(usize::from)
+            .unwrap_or(1)
+            .min(trees.len())
+            .max(1);
+        let chunk_size = trees.len().div_ceil(workers).max(1);
+        thread::scope(|scope| {
+            let handles: Vec<_> = trees
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        let mut expectation = Expectation::new(self);
+                        for tree in chunk {
+                            self.tree_expectation(tree, frequencies, &mut expectation);
+                        }
+                        expectation
+                    })
+                })
+                .collect();
+            let mut total = Expectation::new(self);
+            for handle in handles {
+                total.add(handle.join().expect("expectation thread panicked"));
+            }
+            total
+        })
+    }
+
+    /// adds the expected counts of one tree to `expectation`
+    ///
+    /// inside and outside weights are kept normalised with their logarithmic scale beside them
+    /// so that long sentences do not underflow
+    fn tree_expectation(
+        &self,
+        tree: &[Node],
+        frequencies: Option<&[Vec<f64>]>,
+        expectation: &mut Expectation,
+    ) {
+        let mut inside: Vec<Vec<f64>> = Vec::with_capacity(tree.len());
+        let mut inside_scale = Vec::with_capacity(tree.len());
+        for node in tree {
+            let rule = &self.rules[node.rule];
+            let sizes = rule.sizes(&self.splits);
+            let mut weights = vec![0.0; sizes[0]];
+            let mut scale = 0.0;
+            match node.children[..] {
+                [] => weights.clone_from(&rule.weights),
+                [child] => {
+                    for (index, weight) in rule.weights.iter().enumerate() {
+                        weights[index / sizes[1]] += weight * inside[child][index % sizes[1]];
+                    }
+                    scale = inside_scale[child];
+                }
+                [left, right] => {
+                    for (index, weight) in rule.weights.iter().enumerate() {
+                        let (a, b, c) = (
+                            index / (sizes[1] * sizes[2]),
+                            index / sizes[2] % sizes[1],
+                            index % sizes[2],
+                        );
+                        weights[a] += weight * inside[left][b] * inside[right][c];
+                    }
+                    scale = inside_scale[left] + inside_scale[right];
+                }
+                _ => unreachable!("the trees are binarised"),
+            }
+            let total: f64 = weights.iter().sum();
+            if total <= 0.0 {
+                // the tree has no derivation in the split grammar
+                return;
+            }
+            weights.iter_mut().for_each(|e| *e /= total);
+            inside.push(weights);
+            inside_scale.push(scale + total.ln());
+        }
+        let root = tree.len() - 1;
+        let log_probability = inside[root].iter().sum::<f64>().ln() + inside_scale[root];
+        expectation.log_likelihood += log_probability;
+
+        let mut outside: Vec<Vec<f64>> = inside.iter().map(|e| vec![0.0; e.len()]).collect();
+        let mut outside_scale = vec![0.0; tree.len()];
+        outside[root].iter_mut().for_each(|e| *e = 1.0);
+        for (position, node) in tree.iter().enumerate().rev() {
+            let rule = &self.rules[node.rule];
+            let sizes = rule.sizes(&self.splits);
+            let total: f64 = outside[position].iter().sum();
+            if total <= 0.0 {
+                continue;
+            }
+            outside[position].iter_mut().for_each(|e| *e /= total);
+            outside_scale[position] += total.ln();
+            let parent = outside[position].clone();
+            let parent_scale = outside_scale[position];
+
+            let posterior = (parent_scale + inside_scale[position] - log_probability).exp();
+            let symbol = rule.lhs;
+            for (subcategory, (inside, outside)) in inside[position].iter().zip(&parent).enumerate()
+            {
+                expectation.frequencies[symbol][subcategory] += inside * outside * posterior;
+            }
+            if let Some(frequencies) = frequencies {
+                let all: f64 = inside[position]
+                    .iter()
+                    .zip(&parent)
+                    .map(|(inside, outside)| inside * outside)
+                    .sum();
+                for pair in 0..self.splits[symbol] / 2 {
+                    let (first, second) = (2 * pair, 2 * pair + 1);
+                    let frequency = frequencies[symbol][first] + frequencies[symbol][second];
+                    if frequency <= 0.0 || all <= 0.0 {
+                        continue;
+                    }
+                    let first_share = frequencies[symbol][first] / frequency;
+                    let merged_inside = first_share * inside[position][first]
+                        + (1.0 - first_share) * inside[position][second];
+                    let merged = all
+                        - inside[position][first] * parent[first]
+                        - inside[position][second] * parent[second]
+                        + merged_inside * (parent[first] + parent[second]);
+                    expectation.merge_losses[symbol][pair] += (merged / all).ln();
+                }
+            }
+
+            let counts = &mut expectation.counts[node.rule];
+            match node.children[..] {
+                [] => {
+                    let factor = (parent_scale - log_probability).exp();
+                    for (subcategory, weight) in rule.weights.iter().enumerate() {
+                        counts[subcategory] += parent[subcategory] * weight * factor;
+                    }
+                }
+                [child] => {
+                    let factor = (parent_scale + inside_scale[child] - log_probability).exp();
+                    for (index, weight) in rule.weights.iter().enumerate() {
+                        let (a, b) = (index / sizes[1], index % sizes[1]);
+                        let weight = parent[a] * weight;
+                        counts[index] += weight * inside[child][b] * factor;
+                        outside[child][b] += weight;
+                    }
+                    outside_scale[child] = parent_scale;
+                }
+                [left, right] => {
+                    let factor = (parent_scale + inside_scale[left] + inside_scale[right]
+                        - log_probability)
+                        .exp();
+                    for (index, weight) in rule.weights.iter().enumerate() {
+                        let (a, b, c) = (
+                            index / (sizes[1] * sizes[2]),
+                            index / sizes[2] % sizes[1],
+                            index % sizes[2],
+                        );
+                        let weight = parent[a] * weight;
+                        counts[index] += weight * inside[left][b] * inside[right][c] * factor;
+                        outside[left][b] += weight * inside[right][c];
+                        outside[right][c] += weight * inside[left][b];
+                    }
+                    outside_scale[left] = parent_scale + inside_scale[right];
+                    outside_scale[right] = parent_scale + inside_scale[left];
+                }
+                _ => unreachable!("the trees are binarised"),
+            }
+        }
+    }
+
+    /// returns the split grammar with subcategories written as `NP_3`
+    fn to_grammar(&self) -> HashMap<String, HashMap<induce::Rhs, f64>> {
+        let name = |symbol: usize, subcategory: usize| {
+            let subcategory = (self.splits[symbol] > 1).then_some(subcategory);
+            latent_label(&self.names[symbol], subcategory)
+        };
+        let mut grammar: HashMap<String, HashMap<induce::Rhs, f64>> = HashMap::new();
+        for rule in &self.rules {
+            let symbols: Vec<usize> = std::iter::once(rule.lhs)
+                .chain(rule.children.iter().copied())
+                .collect();
+            let sizes = rule.sizes(&self.splits);
+            for (index, weight) in rule.weights.iter().enumerate() {
+                if *weight <= 0.0 {
+                    continue;
+                }
+                let subcategories = unravel(index, &sizes);
+                let rhs = match &rule.word {
+                    Some(word) => induce::Rhs::Terminal(word.clone()),
+                    None => induce::Rhs::NonTerminals(
+                        symbols[1..]
+                            .iter()
+                            .zip(&subcategories[1..])
+                            .map(|(symbol, subcategory)| name(*symbol, *subcategory))
+                            .collect(),
+                    ),
+                };
+                grammar
+                    .entry(name(rule.lhs, subcategories[0]))
+                    .or_default()
+                    .insert(rhs, *weight);
+            }
+        }
+        grammar
+    }
+}
+
+fn name(string_lookup: &StringLookup, item: Item) -> String {
+    string_lookup
+        .get_string(usize::from(item))
+        .expect("every item is in the lookup")
+        .clone()
+}
+
+/// splits a flat tensor index into the index of each dimension
+fn unravel(mut index: usize, sizes: &[usize]) -> Vec<usize> {
+    let mut indices = vec![0; sizes.len()];
+    for (position, size) in sizes.iter().enumerate().rev() {
+        indices[position] = index % size;
+        index /= size;
+    }
+    indices
+}
+
+fn ravel(indices: &[usize], sizes: &[usize]) -> usize {
+    indices
+        .iter()
+        .zip(sizes)
+        .fold(0, |index, (position, size)| index * size + position)
+}
+
+/// maps an index of a split tensor to the index of the tensor before the split
+fn project_index(index: usize, new_sizes: &[usize], old_sizes: &[usize]) -> usize {
+    let indices: Vec<usize> = unravel(index, new_sizes)
+        .iter()
+        .zip(new_sizes.iter().zip(old_sizes))
+        .map(|(position, (new, old))| position / (new / old))
+        .collect();
+    ravel(&indices, old_sizes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{induce::parse_tree::element, parse::insert_into_lookup};
+
+    fn latent_grammar(rules: &[&str], lexicon: &[&str]) -> LatentGrammar {
+        let mut string_map = StringLookup::default();
+        let mut rule_lookup = HashMap::new();
+        let mut all_rules = HashMap::new();
+        for line in lexicon {
+            insert_into_lookup(
+                &mut string_map,
+                false,
+                &mut rule_lookup,
+                &mut all_rules,
+                line.to_string(),
+            );
+        }
+        for line in rules {
+            insert_into_lookup(
+                &mut string_map,
+                true,
+                &mut rule_lookup,
+                &mut all_rules,
+                line.to_string(),
+            );
+        }
+        let start = Item::NonTerminal(string_map.get("ROOT").unwrap() as u32);
+        LatentGrammar::new(&all_rules, &string_map, start)
+    }
+
+    #[test]
+    fn project_test() {
+        assert_eq!(project("NP_12"), "NP");
+        assert_eq!(project("NP"), "NP");
+        assert_eq!(project("_1"), "_1");
+        assert_eq!(project("NP_"), "NP_");
+        assert_eq!(project("NP_a"), "NP_a");
+        assert_eq!(project("NP\\_1_2"), "NP_1");
+        assert_eq!(project("NP\\_1"), "NP_1");
+        assert_eq!(project("-NONE-\\_2_0"), "-NONE-_2");
+        assert_eq!(project("a\\\\_1"), "a\\");
+        for label in ["NP_1", "a\\ b", "_", "x\\_7"] {
+            assert_eq!(project(&latent_label(label, Some(3))), label);
+            assert_eq!(project(&latent_label(label, None)), label);
+        }
+
+        // labels of the original grammar that end in _N are not merged with the ones without
+        let grammar = latent_grammar(&["ROOT -> NP_1 NP 1"], &["NP_1 a 1", "NP b 1"]);
+        let written = grammar.to_grammar();
+        let rhs = induce::Rhs::NonTerminals(vec!["NP\\_1".to_string(), "NP".to_string()]);
+        assert_eq!(written["ROOT"][&rhs], 1.0);
+        assert_eq!(project("NP\\_1"), "NP_1");
+    }
+
+    #[test]
+    fn index_test() {
+        assert_eq!(unravel(5, &[2, 3]), vec![1, 2]);
+        assert_eq!(ravel(&[1, 2], &[2, 3]), 5);
+        assert_eq!(project_index(7, &[2, 4], &[1, 2]), 1);
+    }
+
+    #[test]
+    fn split_keeps_likelihood_test() {
+        let mut grammar = latent_grammar(
+            &["ROOT -> A B 1", "A -> B 0.5", "B -> A B 0.5"],
+            &["A a 0.5", "B b 0.5"],
+        );
+        let (_, tree) = element("(ROOT (A (B b)) (B (A a) (B b)))").unwrap();
+        let trees = vec![grammar.tree_nodes(&tree).unwrap()];
+        let before = grammar.expectation(&trees, None).log_likelihood;
+        assert!((before - (0.5f64 * 0.5 * 0.5 * 0.5 * 0.5).ln()).abs() < 1e-12);
+        grammar.split(&mut Random::new(0));
+        assert_eq!(grammar.splits, vec![1, 2, 2]);
+        let after = grammar.expectation(&trees, None).log_likelihood;
+        assert!((before - after).abs() < 0.05);
+        let expectation = grammar.expectation(&trees, None);
+        grammar.maximize(&expectation.counts);
+        assert!(grammar.expectation(&trees, None).log_likelihood >= after - 1e-9);
+    }
+
+    #[test]
+    fn merge_test() {
+        let mut grammar = latent_grammar(&["ROOT -> A A 1"], &["A a 0.5", "A b 0.5"]);
+        let (_, tree) = element("(ROOT (A a) (A b))").unwrap();
+        let trees = vec![grammar.tree_nodes(&tree).unwrap()];
+        grammar.split(&mut Random::new(1));
+        let expectation = grammar.expectation(&trees, None);
+        let losses = grammar.expectation(&trees, Some(&expectation.frequencies));
+        assert!(losses.merge_losses[1][0].abs() < 1e-3);
+        grammar.merge(&expectation.counts, &losses.merge_losses, 1.0);
+        assert_eq!(grammar.splits, vec![1, 1]);
+        let merged = grammar.to_grammar();
+        assert!((merged["A"][&induce::Rhs::Terminal("a".to_string())] - 0.5).abs() < 0.01);
+        let (_, tree) = element("(ROOT (A a) (A a) (A a))").unwrap();
+        assert!(grammar.tree_nodes(&tree).is_err());
+    }
+}
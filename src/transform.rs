@@ -0,0 +1,164 @@
+use std::{io, process::exit};
+
+use clap::ValueEnum;
+
+use crate::{
+    induce::parse_tree::{ParseTree, read_trees, restore_brackets, treebank_element},
+    output::{OutputFormat, format_tree},
+};
+
+/// a preprocessing step of the treebank, the steps run in the order they are given
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Step {
+    /// remove function tags and indices (`NP-TMP-1` → `NP`)
+    StripFunctionTags,
+    /// delete `-NONE-` empty elements and the constituents that are left without words
+    DeleteEmpty,
+    /// collapse unary chains `X -> X` into a single `X`
+    CollapseUnary,
+    /// give the empty-label root `( (S …))` of the Penn Treebank a label
+    AddRoot,
+}
+
+/// the steps that run if none are chosen
+pub const DEFAULT_STEPS: [Step; 4] = [
+    Step::StripFunctionTags,
+    Step::DeleteEmpty,
+    Step::CollapseUnary,
+    Step::AddRoot,
+];
+
+pub fn transform(steps: &[Step], root: &str, restore: bool, output_format: OutputFormat) {
+    let steps = if steps.is_empty() {
+        &DEFAULT_STEPS[..]
+    } else {
+        steps
+    };
+    for tree_text in read_trees(io::stdin().lock(), "stdin") {
+        let tree = match treebank_element(&tree_text.text) {
+            Ok((remaining, tree)) if remaining.trim().is_empty() => tree,
+            _ => {
+                eprintln!("stdin, {}", tree_text.error("malformed tree"));
+                exit(1);
+            }
+        };
+        // a tree that consists only of empty elements is left out
+        if let Some(mut tree) = transform_tree(tree, steps, root) {
+            if restore {
+                restore_brackets(&mut tree);
+            }
+            println!("{}", format_tree(&tree, output_format));
+        }
+    }
+}
+
+pub fn transform_tree<'a>(
+    tree: ParseTree<&'a str>,
+    steps: &[Step],
+    root: &'a str,
+) -> Option<ParseTree<&'a str>> {
+    steps.iter().try_fold(tree, |tree, step| match step {
+        Step::StripFunctionTags => Some(strip_function_tags_tree(tree)),
+        Step::DeleteEmpty => delete_empty(tree),
+        Step::CollapseUnary => Some(collapse_unary(tree)),
+        Step::AddRoot => Some(add_root(tree, root)),
+    })
+}
+
+/// removes function tags and indices from a label, labels starting with `-` like `-NONE-`
+/// or `-LRB-` are left as they are
+pub fn strip_function_tags(label: &str) -> &str {
+    if label.starts_with('-') {
+        label
+    } else {
+        label.split(['-', '=']).next().unwrap()
+    }
+}
+
+fn strip_function_tags_tree(mut tree: ParseTree<&str>) -> ParseTree<&str> {
+    tree.change_nodes(&mut |node| {
+        if !node.is_leaf() {
+            node.root = strip_function_tags(node.root);
+        }
+    });
+    tree
+}
+
+/// returns `None` if the tree has no words left
+fn delete_empty(tree: ParseTree<&str>) -> Option<ParseTree<&str>> {
+    if tree.is_leaf() {
+        return Some(tree);
+    }
+    if tree.root == "-NONE-" {
+        return None;
+    }
+    let children: Vec<_> = tree.children.into_iter().filter_map(delete_empty).collect();
+    if children.is_empty() {
+        None
+    } else {
+        Some(ParseTree {
+            root: tree.root,
+            children,
+        })
+    }
+}
+
+fn collapse_unary(tree: ParseTree<&str>) -> ParseTree<&str> {
+    let mut tree = ParseTree {
+        root: tree.root,
+        children: tree.children.into_iter().map(collapse_unary).collect(),
+    };
+    if tree.children.len() == 1
+        && !tree.children[0].is_leaf()
+        && tree.children[0].root == tree.root
+    {
+        tree = tree.children.pop().unwrap();
+    }
+    tree
+}
+
+fn add_root<'a>(mut tree: ParseTree<&'a str>, root: &'a str) -> ParseTree<&'a str> {
+    if tree.root.is_empty() {
+        tree.root = root;
+    }
+    tree
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TREE: &str = "( (S (NP-SBJ-1 (NNP John)) (VP (VBD left) (S (NP-SBJ (-NONE- *-1)) \
+                        (VP (TO to) (VP (VB go))))) (. .)) )";
+
+    fn transform_line(steps: &[Step]) -> String {
+        let (_, tree) = treebank_element(TREE).unwrap();
+        transform_tree(tree, steps, "ROOT").unwrap().to_string()
+    }
+
+    #[test]
+    fn steps_test() {
+        assert_eq!(
+            transform_line(&[Step::StripFunctionTags, Step::AddRoot]),
+            "(ROOT (S (NP (NNP John)) (VP (VBD left) (S (NP (-NONE- *-1)) (VP (TO to) (VP (VB go))))) (. .)))"
+        );
+        assert_eq!(
+            transform_line(&[Step::DeleteEmpty]),
+            "( (S (NP-SBJ-1 (NNP John)) (VP (VBD left) (S (VP (TO to) (VP (VB go))))) (. .)))"
+        );
+        assert_eq!(
+            transform_line(&DEFAULT_STEPS),
+            "(ROOT (S (NP (NNP John)) (VP (VBD left) (S (VP (TO to) (VP (VB go))))) (. .)))"
+        );
+    }
+
+    #[test]
+    fn collapse_unary_test() {
+        let (_, tree) = treebank_element("(ROOT (NP (NP (NP (NN it)))))").unwrap();
+        assert_eq!(collapse_unary(tree).to_string(), "(ROOT (NP (NN it)))");
+        let (_, tree) = treebank_element("(ROOT (NN NN))").unwrap();
+        assert_eq!(collapse_unary(tree).to_string(), "(ROOT (NN NN))");
+        let (_, tree) = treebank_element("( (-NONE- *))").unwrap();
+        assert_eq!(delete_empty(tree), None);
+    }
+}
@@ -1,52 +1,288 @@
-use std::{io, process::exit};
+use std::{
+    borrow::Borrow,
+    hash::Hash,
+    io::{self, BufRead},
+    path::{Path, PathBuf},
+};
 
 use foldhash::{HashMap, HashSet};
 
-use crate::induce::parse_tree::{ParseTree, element};
-
-pub fn unk(threshold: u64) {
-    let mut word_count: HashMap<&str, u64> = HashMap::default();
-    let string_lines: Vec<String> = io::stdin().lines().map_while(Result::ok).collect();
-    let trees =  get_tree_lines(&string_lines, &mut word_count);
-    let unknown_words: HashSet<&str> = word_count
-        .into_iter()
-        .filter_map(
-            |(word, count)| {
-                if count <= threshold { Some(word) } else { None }
-            },
-        )
-        .collect();
-    for mut line in trees {
-        line.change_nodes(&mut |e| {
-            if e.is_leaf() && unknown_words.contains(&e.root) {
-                e.root = "UNK";
+use crate::induce::{
+    Rhs, parse_count_line,
+    parse_tree::{ParseTree, TreeText, open_trees, read_or_exit, read_trees, sentence_tokens},
+};
+
+/// the words that are kept, all other words are mapped to an unknown word class
+pub struct Vocabulary {
+    known: HashSet<String>,
+}
+
+impl Vocabulary {
+    /// the words that occur more than `threshold` times
+    pub fn from_counts<'a>(
+        counts: impl IntoIterator<Item = (&'a str, u64)>,
+        threshold: u64,
+    ) -> Self {
+        Self {
+            known: counts
+                .into_iter()
+                .filter(|(_, count)| *count > threshold)
+                .map(|(word, _)| word.to_string())
+                .collect(),
+        }
+    }
+
+    /// reads a `.words` file with one word per line, all of its words are known, or a `.counts`
+    /// file, where the words that occur more than `threshold` times in the lexical rules are
+    /// known
+    pub fn read(reader: impl BufRead, threshold: u64) -> Result<Self, String> {
+        let mut known = HashSet::default();
+        let mut counts: HashMap<String, u64> = HashMap::default();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
             }
-        });
-        println!("{}", line);
+            let mut tokens = sentence_tokens(line);
+            match parse_count_line(line) {
+                Some((_, Rhs::Terminal(word), count)) => {
+                    *counts.entry(word).or_default() += count;
+                }
+                Some((_, Rhs::NonTerminals(_), _)) => {}
+                None if tokens.len() == 1 => {
+                    known.extend(tokens.pop());
+                }
+                None => {
+                    return Err(format!(
+                        "line {}: not a word or count: {}",
+                        number + 1,
+                        line
+                    ));
+                }
+            }
+        }
+        let counted = Self::from_counts(counts.iter().map(|(e, c)| (&e[..], *c)), threshold);
+        known.extend(counted.known);
+        Ok(Self { known })
+    }
+
+    pub fn from_file(path: &Path, threshold: u64) -> Self {
+        read_or_exit(path, "vocabulary", |reader| Self::read(reader, threshold))
     }
+
+    pub fn contains(&self, word: &str) -> bool {
+        self.known.contains(word)
+    }
+}
+
+/// calls `rewrite` for every tree with the vocabulary from the file or, without a file, from
+/// the word counts of the trees
+///
+/// without `inputs` the trees are read from `stdin` and kept in memory if the words have to be
+/// counted, otherwise the files are read twice: once to count the words and once to rewrite the
+/// trees
+pub fn rewrite_trees<F>(
+    stdin: impl BufRead,
+    inputs: &[PathBuf],
+    vocabulary: &Option<PathBuf>,
+    threshold: u64,
+    mut rewrite: F,
+) where
+    F: FnMut(ParseTree<&str>, &Vocabulary),
+{
+    if let Some(path) = vocabulary {
+        let vocabulary = Vocabulary::from_file(path, threshold);
+        if inputs.is_empty() {
+            for tree_text in read_trees(stdin, "stdin") {
+                rewrite(tree_text.parse_or_exit("stdin"), &vocabulary);
+            }
+        }
+        rewrite_files(inputs, &vocabulary, &mut rewrite);
+        return;
+    }
+    if inputs.is_empty() {
+        let tree_texts: Vec<TreeText> = read_trees(stdin, "stdin").collect();
+        let mut word_count: HashMap<&str, u64> = HashMap::default();
+        let trees = get_tree_lines(&tree_texts, "stdin", &mut word_count);
+        let vocabulary = Vocabulary::from_counts(word_count, threshold);
+        for tree in trees {
+            rewrite(tree, &vocabulary);
+        }
+        return;
+    }
+    let word_count = count_words(inputs);
+    let vocabulary =
+        Vocabulary::from_counts(word_count.iter().map(|(e, c)| (&e[..], *c)), threshold);
+    rewrite_files(inputs, &vocabulary, &mut rewrite);
+}
+
+fn rewrite_files<F>(inputs: &[PathBuf], vocabulary: &Vocabulary, rewrite: &mut F)
+where
+    F: FnMut(ParseTree<&str>, &Vocabulary),
+{
+    for path in inputs {
+        let source = path.display().to_string();
+        for tree_text in open_trees(path) {
+            rewrite(tree_text.parse_or_exit(&source), vocabulary);
+        }
+    }
+}
+
+/// counts the words of the trees in the files without keeping the trees
+fn count_words(inputs: &[PathBuf]) -> HashMap<String, u64> {
+    let mut word_count: HashMap<String, u64> = HashMap::default();
+    for path in inputs {
+        let source = path.display().to_string();
+        for tree_text in open_trees(path) {
+            count_leaves(&tree_text.parse_or_exit(&source), &mut word_count);
+        }
+    }
+    word_count
+}
+
+/// adds the words of the tree to `word_count`
+fn count_leaves<'a, K>(tree: &ParseTree<&'a str>, word_count: &mut HashMap<K, u64>)
+where
+    K: Borrow<str> + From<&'a str> + Hash + Eq,
+{
+    tree.execute_for_nodes(&mut |tree| {
+        if tree.is_leaf() {
+            match word_count.get_mut(tree.root) {
+                Some(count) => *count += 1,
+                None => {
+                    word_count.insert(K::from(tree.root), 1);
+                }
+            }
+        }
+    });
+}
+
+pub fn unk(threshold: u64, vocabulary: &Option<PathBuf>, inputs: &[PathBuf]) {
+    rewrite_trees(
+        io::stdin().lock(),
+        inputs,
+        vocabulary,
+        threshold,
+        |tree, vocabulary| println!("{}", unk_tree(tree, vocabulary)),
+    );
+}
+
+/// replaces the unknown words of the tree with `UNK`
+fn unk_tree<'a>(mut tree: ParseTree<&'a str>, vocabulary: &Vocabulary) -> ParseTree<&'a str> {
+    tree.change_nodes(&mut |e| {
+        if e.is_leaf() && !vocabulary.contains(e.root) {
+            e.root = "UNK";
+        }
+    });
+    tree
 }
 
 pub fn get_tree_lines<'a>(
-    lines: &'a [String],
+    tree_texts: &'a [TreeText],
+    source: &str,
     word_count: &mut HashMap<&'a str, u64>,
 ) -> Vec<ParseTree<&'a str>> {
     let mut trees = Vec::new();
-    for (line_number, line) in lines.iter().enumerate() {
-        let Ok((rem, tree)) = element(line) else {
-            eprintln!("error while parsing line {}", line_number + 1);
-            exit(1);
-        };
-        if rem.trim() != "" {
-            eprintln!("line {} not completely parsed", line_number + 1);
-            exit(1);
-        }
-        tree.execute_for_nodes(&mut |tree| {
-            if tree.is_leaf() {
-                let count: &mut u64 = word_count.entry(tree.root).or_default();
-                *count += 1;
-            }
-        });
+    for tree_text in tree_texts {
+        let tree = tree_text.parse_or_exit(source);
+        count_leaves(&tree, word_count);
         trees.push(tree);
     }
     trees
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::smoothing::{SignatureScheme, smooth_tree};
+
+    #[test]
+    fn vocabulary_test() {
+        let words = Vocabulary::read("the\ndog\nNew\\ York\n".as_bytes(), 5).unwrap();
+        assert!(words.contains("dog"));
+        assert!(words.contains("New\\ York"));
+        assert!(!words.contains("cat"));
+
+        let counts = "S -> NP VP 3\nNN dog 2\nVB dog 1\nNN cat 2\nDT the 5\n";
+        let counts = Vocabulary::read(counts.as_bytes(), 2).unwrap();
+        assert!(counts.contains("dog"));
+        assert!(counts.contains("the"));
+        assert!(!counts.contains("cat"));
+        assert!(!counts.contains("NP"));
+
+        assert!(Vocabulary::read("NN dog 0.5\n".as_bytes(), 2).is_err());
+    }
+
+    /// the rewritten trees with the trees from stdin and from files in two passes
+    fn rewrite_both(
+        name: &str,
+        vocabulary: &Option<PathBuf>,
+        rewrite: impl Fn(ParseTree<&str>, &Vocabulary) -> String,
+    ) -> (Vec<String>, Vec<String>) {
+        let trees = [
+            "(S (NP (DT the) (NN dog)) (VP (VBZ barks)))",
+            "(S (NP (NN Dogs)) (VP (VBD ran)))",
+        ];
+        let mut from_stdin = Vec::new();
+        let stdin = trees.join("\n");
+        rewrite_trees(stdin.as_bytes(), &[], vocabulary, 0, |tree, vocabulary| {
+            from_stdin.push(rewrite(tree, vocabulary))
+        });
+        let inputs: Vec<PathBuf> = trees
+            .iter()
+            .enumerate()
+            .map(|(i, tree)| {
+                let path = std::env::temp_dir().join(format!(
+                    "pcfg_tool_{}_{}_{}.mrg",
+                    name,
+                    std::process::id(),
+                    i
+                ));
+                std::fs::write(&path, format!("{tree}\n{tree}\n")).unwrap();
+                path
+            })
+            .collect();
+        let mut from_files = Vec::new();
+        rewrite_trees("".as_bytes(), &inputs, vocabulary, 1, |tree, vocabulary| {
+            from_files.push(rewrite(tree, vocabulary))
+        });
+        for path in inputs {
+            std::fs::remove_file(path).unwrap();
+        }
+        (from_stdin, from_files.into_iter().step_by(2).collect())
+    }
+
+    fn unk_line(tree: ParseTree<&str>, vocabulary: &Vocabulary) -> String {
+        unk_tree(tree, vocabulary).to_string()
+    }
+
+    fn smooth_line(tree: ParseTree<&str>, vocabulary: &Vocabulary) -> String {
+        smooth_tree(tree, vocabulary, &SignatureScheme::default(), &mut 0).to_string()
+    }
+
+    #[test]
+    fn two_pass_test() {
+        // every tree is in its file twice, so the threshold of the files is 1 instead of 0
+        let (from_stdin, from_files) = rewrite_both("unk", &None, unk_line);
+        assert_eq!(from_stdin, from_files);
+        assert_eq!(from_stdin[0], "(S (NP (DT the) (NN dog)) (VP (VBZ barks)))");
+
+        let (from_stdin, from_files) = rewrite_both("smooth", &None, smooth_line);
+        assert_eq!(from_stdin, from_files);
+
+        let words = std::env::temp_dir().join(format!("pcfg_tool_{}.words", std::process::id()));
+        std::fs::write(&words, "the\ndog\nran\n").unwrap();
+        let vocabulary = Some(words.clone());
+        let (from_stdin, from_files) = rewrite_both("unk_words", &vocabulary, unk_line);
+        assert_eq!(from_stdin, from_files);
+        assert_eq!(from_stdin[1], "(S (NP (NN UNK)) (VP (VBD ran)))");
+        let (from_stdin, from_files) = rewrite_both("smooth_words", &vocabulary, smooth_line);
+        assert_eq!(from_stdin, from_files);
+        assert_eq!(
+            from_stdin[0],
+            "(S (NP (DT the) (NN dog)) (VP (VBZ UNK-L-s)))"
+        );
+        std::fs::remove_file(words).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+use std::{io, process::exit};
+
+use foldhash::{HashMap, HashMapExt};
+
+use crate::{
+    binarise::{Binarisation, binarise_tree},
+    debinarise::debinarise_tree,
+    induce::parse_tree::{ParseTree, element, read_trees},
+    show::brackets,
+};
+
+/// binarises and debinarises the trees from stdin and reports the ones that change
+pub fn verify_transform(binarisation: &Binarisation) {
+    let mut total = 0;
+    let mut failed = 0;
+    for tree_text in read_trees(io::stdin().lock(), "stdin") {
+        total += 1;
+        let tree = tree_text.parse_or_exit("stdin");
+        let binarised = binarise_tree(tree, binarisation, &[], None).to_string();
+        let Ok((_, binarised_tree)) = element(&binarised) else {
+            eprintln!("cannot read the binarised tree {}", binarised);
+            exit(1);
+        };
+        let restored = debinarise_tree(binarised_tree, &binarisation.markers);
+        let tree = tree_text.parse_or_exit("stdin");
+        if restored.to_string() == tree.to_string() {
+            continue;
+        }
+        failed += 1;
+        println!(
+            "tree {} (line {}) does not round-trip",
+            total, tree_text.line
+        );
+        println!("  original:    {}", tree);
+        println!("  binarised:   {}", binarised);
+        println!("  debinarised: {}", restored);
+        for line in bracket_diff(&tree, &restored) {
+            println!("  {}", line);
+        }
+    }
+    println!("{} of {} trees do not round-trip", failed, total);
+    if failed > 0 {
+        exit(1);
+    }
+}
+
+/// returns `- label start end` for the brackets that only the original tree has and
+/// `+ label start end` for the ones that only the restored tree has
+fn bracket_diff<T: AsRef<str>, R: AsRef<str>>(
+    original: &ParseTree<T>,
+    restored: &ParseTree<R>,
+) -> Vec<String> {
+    let mut original_brackets = Vec::new();
+    brackets(original, &mut 0, &mut original_brackets);
+    let mut restored_brackets = Vec::new();
+    brackets(restored, &mut 0, &mut restored_brackets);
+
+    let mut counts: HashMap<(&str, usize, usize), i64> = HashMap::new();
+    for bracket in original_brackets.iter().flatten() {
+        *counts.entry(*bracket).or_default() += 1;
+    }
+    for bracket in restored_brackets.iter().flatten() {
+        *counts.entry(*bracket).or_default() -= 1;
+    }
+    let mut diff = Vec::new();
+    for (bracket, sign) in [(&original_brackets, "-"), (&restored_brackets, "+")] {
+        for (label, start, end) in bracket.iter().flatten() {
+            let count = counts.get_mut(&(*label, *start, *end)).unwrap();
+            let missing = if sign == "-" { *count > 0 } else { *count < 0 };
+            if missing {
+                diff.push(format!("{} {} {} {}", sign, label, start, end));
+                *count += if sign == "-" { -1 } else { 1 };
+            }
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bracket_diff_test() {
+        let (_, original) = element("(S (NP (DT a) (NN b)) (VP (VB c)))").unwrap();
+        let (_, restored) = element("(S (NP|<DT,NN> (DT a) (NN b)) (VP (VB c)))").unwrap();
+        assert_eq!(
+            bracket_diff(&original, &restored),
+            vec!["- NP 0 2", "+ NP|<DT,NN> 0 2"]
+        );
+        assert!(bracket_diff(&original, &original).is_empty());
+    }
+}